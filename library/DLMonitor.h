@@ -3,6 +3,7 @@
 
 #include "llvm/IR/Module.h"
 #include "llvm/Support/FileSystem.h"
+#include "llvm/Analysis/LazyCallGraph.h"
 #include "llvm/Analysis/LoopInfo.h"
 #include "llvm/Analysis/LoopNestAnalysis.h"
 #include "llvm/ADT/Hashing.h"
@@ -17,9 +18,12 @@ using LineInfo = unsigned;
 ///        Statically collect debug locations on simple paths in given CFG.
 class DebugLocInfo {
 public:
-    DebugLocInfo(Function *F) {
-        // outs() << "|===============<" << F->getName() << ">===============|\n";
-        collect(F);
+    DebugLocInfo(ArrayRef<Function *> Fs) {
+        for (Function *F: Fs) {
+            // outs() << "|===============<" << F->getName() << ">===============|\n";
+            if (!F->isDeclaration())
+                collect(F);
+        }
     }
 
     bool containsInst(hash_code InstHash) const {
@@ -148,6 +152,8 @@ enum class UpdateKind {
     Preserve,
     Merge,
     Drop,
+    /// A location neither kept nor merged nor dropped, e.g., `DebugLoc::getCompilerGenerated()`
+    Unknown,
     None,
 };
 
@@ -163,6 +169,9 @@ enum class Event {
     Clone,
     Move,
     UseReplace,
+    BuilderCreate,
+    Insert,
+    Replace,
 };
 
 class DLStat {
@@ -178,6 +187,11 @@ public:
 
     InstKind getInstKind() const { return IK; }
 
+    /// The IRBuilder creating the instruction, if any
+    void setBuilder(StringRef BN) { BuilderName = BN; }
+    bool isBuilderCreated() const { return !BuilderName.empty(); }
+    StringRef getBuilderName() const { return BuilderName; }
+
     void addEvent(Event E, unsigned SrcLine) {
         Events.push_back({E, SrcLine});
     }
@@ -192,21 +206,32 @@ public:
                 outs << "(" << "Move" << ", " << E.second << ")";
             if (E.first == Event::UseReplace)
                 outs << "(" << "UseReplace" << ", " << E.second << ")";
+            if (E.first == Event::BuilderCreate)
+                outs << "(" << "BuilderCreate" << ", " << E.second << ")";
+            if (E.first == Event::Insert)
+                outs << "(" << "Insert" << ", " << E.second << ")";
+            if (E.first == Event::Replace)
+                outs << "(" << "Replace" << ", " << E.second << ")";
         }   
     }
 private:
     InstKind IK;
     StringRef VarName;
     LineInfo SrcLine;
+    StringRef BuilderName;
 
     DenseSet<Inst> Srcs;
     SmallVector<std::pair<Event, LineInfo>> Events;
 public:
-    void setDebugLocUpdate(UpdateKind Kind, LineInfo SrcLine) {
+    void setDebugLocUpdate(UpdateKind Kind, LineInfo SrcLine, StringRef Expr = "") {
         UK = Kind;
         UpdateLine = SrcLine;
+        UpdateExpr = Expr;
     }
 
+    /// The location set by the last update, e.g., `DebugLoc()`, if known
+    StringRef getUpdateExpr() const { return UpdateExpr; }
+
     std::pair<UpdateKind, LineInfo> getDebugLocUpdate() const {
         if (UK != UpdateKind::None)
             return { UK, UpdateLine };
@@ -220,6 +245,7 @@ public:
 private:
     UpdateKind UK;
     unsigned UpdateLine;
+    StringRef UpdateExpr;
 };
 
 /// @brief Debug Location Monitor
@@ -229,10 +255,10 @@ public:
     DenseMap<hash_code, Inst> BBToNewTerm; // Map a basic block to its terminator
     DenseMap<hash_code, Inst> BBToOldTerm; // Map a basic block to its terminator
 
-    DLMonitor(Function &F, StringRef PN)
-        : PassName(PN), TargetF(&F) 
+    DLMonitor(ArrayRef<Function *> Fs, StringRef PN)
+        : PassName(PN), TargetFs(Fs.begin(), Fs.end())
     {
-        DebugLocBeforeOpt = new DebugLocInfo(&F);
+        DebugLocBeforeOpt = new DebugLocInfo(TargetFs);
 
         // Open the log output stream
         // StringRef LogDir = "./tmp";
@@ -248,7 +274,7 @@ public:
     }
 
     void onOptFinished() {
-        DebugLocAfterOpt = new DebugLocInfo(TargetF);
+        DebugLocAfterOpt = new DebugLocInfo(TargetFs);
 
         for (auto [Dst, Stat]: InstToStat) {
             // outs() << "Checking " << Stat->getName() << "...\n";
@@ -289,23 +315,44 @@ public:
 
             auto [UKind, SrcLine] = Stat->getDebugLocUpdate();
 
+            // The location set cannot be checked against the sources
+            if (UKind == UpdateKind::Unknown) {
+                outs().changeColor(outs().YELLOW, true);
+                outs() << "WARNING: ";
+                outs().resetColor();
+                outs() << "LINE " << Stat->getLine() << ", UNKNOWN(" << Stat->getName() << ")";
+                printUpdate(Stat);
+                outs() << "\n";
+                continue;
+            }
+
             if (HasConflict) {
-                if (!checkUpdate(UKind, UpdateKind::Drop))
-                    outs() << "LINE " << Stat->getLine() << ", DROP(" << Stat->getName() << ")\n";
+                if (!checkUpdate(UKind, UpdateKind::Drop)) {
+                    outs() << "LINE " << Stat->getLine() << ", DROP(" << Stat->getName() << ")";
+                    printBuilder(Stat);
+                    printUpdate(Stat);
+                    outs() << "\n";
+                }
             } else {
                 if (NumberOfSrc == 1) {
                     if (!checkUpdate(UKind, UpdateKind::Preserve)) {
                         outs() << "LINE " << Stat->getLine() << ", PRESERVE(" << Stat->getName();
                         for (Inst inst: Srcs)
                             outs() << ", " << inst.second;
-                            outs() << ")\n";
+                        outs() << ")";
+                        printBuilder(Stat);
+                        printUpdate(Stat);
+                        outs() << "\n";
                     }
                 } else {
                     if (!checkUpdate(UKind, UpdateKind::Merge)) {
                         outs() << "LINE " << Stat->getLine() << ", MERGE(" << Stat->getName();
                         for (Inst inst: Srcs)
                             outs() << ", " << inst.second;
-                            outs() << ")\n";
+                        outs() << ")";
+                        printBuilder(Stat);
+                        printUpdate(Stat);
+                        outs() << "\n";
                     }
                 }
             }
@@ -315,6 +362,19 @@ public:
         }
     }
 
+    /// Pair a terminator put into a block with the one removed from the block
+    void onNewTerminator(Instruction *I, StringRef VarName) {
+        hash_code HashOfInst = hash_value(I);
+        hash_code HashOfBB = hash_value(I->getParent());
+        if (!BBToOldTerm.contains(HashOfBB)) {
+            BBToNewTerm[HashOfBB] = {HashOfInst, VarName};
+        } else {
+            InstToStat[HashOfInst]->addSrc(BBToOldTerm[HashOfBB].first, BBToOldTerm[HashOfBB].second);
+            InstToStat[HashOfInst]->addEvent(Event::UseReplace, 0);
+            BBToOldTerm.erase(HashOfBB);
+        }
+    }
+
     bool checkUpdate(UpdateKind UKind, UpdateKind EUKind) {
         if (UKind == EUKind) {
             // outs().changeColor(outs().GREEN, true);
@@ -328,9 +388,21 @@ public:
             return false;
         }
     }
+
+    /// Tell the instructions created by an IRBuilder from the others
+    void printBuilder(DLStat *Stat) {
+        if (Stat->isBuilderCreated())
+            outs() << ", IRBUILDER(" << Stat->getBuilderName() << ")";
+    }
+
+    /// Show the location set by `setDebugLoc`, which the update kind is told from
+    void printUpdate(DLStat *Stat) {
+        if (!Stat->getUpdateExpr().empty())
+            outs() << ", SETDEBUGLOC(" << Stat->getDebugLocUpdate().second << ", " << Stat->getUpdateExpr() << ")";
+    }
 private:
     StringRef PassName;
-    Function *TargetF;
+    SmallVector<Function *> TargetFs;
     raw_fd_ostream *Logs;
 
     DebugLocInfo *DebugLocBeforeOpt;
@@ -340,31 +412,73 @@ private:
 };
 
 namespace hook {
-    DLMonitor *DLM = nullptr;
+    /// The monitor shared by all the instrumented files, set while a pass entry runs
+    inline DLMonitor *DLM = nullptr;
+    /// Number of the pass entries running, e.g., `run` calling `runImpl`,
+    /// only the outermost of which starts and finishes the analysis
+    inline unsigned Depth = 0;
+
     /*
      * Analysis initialization and finalization
      */
-    void OnStart(Function &F, StringRef PassName) {
-        DLM = new DLMonitor(F, PassName);
+    inline void OnFinish() {
+        if (--Depth)
+            return ;
+        DLM->onOptFinished();
+        delete DLM;
+        DLM = nullptr;
     }
 
-    void OnStart(Loop &L, StringRef PassName) {
-        DLM = new DLMonitor(*L.getLoopPreheader()->getParent(), PassName);
+    /// Returned by OnStart and kept in the pass entry, finishing the analysis
+    /// on whichever path the entry exits
+    struct [[nodiscard]] Finisher {
+        Finisher() = default;
+        Finisher(const Finisher &) = delete;
+        Finisher &operator=(const Finisher &) = delete;
+        ~Finisher() { OnFinish(); }
+    };
+
+    inline Finisher OnStart(ArrayRef<Function *> Fs, StringRef PassName) {
+        if (!Depth++)
+            DLM = new DLMonitor(Fs, PassName);
+        return {};
     }
 
-    void OnStart(LoopNest &LN, StringRef PassName) {
-        DLM = new DLMonitor(*LN.getParent(), PassName);
+    inline Finisher OnStart(Function &F, StringRef PassName) {
+        return OnStart(ArrayRef<Function *>(&F), PassName);
     }
 
-    void OnFinish() {
-        DLM->onOptFinished();
-        delete DLM;
+    inline Finisher OnStart(Loop &L, StringRef PassName) {
+        return OnStart(*L.getHeader()->getParent(), PassName);
+    }
+
+    inline Finisher OnStart(LoopNest &LN, StringRef PassName) {
+        return OnStart(*LN.getParent(), PassName);
+    }
+
+    inline Finisher OnStart(Module &M, StringRef PassName) {
+        SmallVector<Function *> Fs;
+        for (Function &F: M)
+            Fs.push_back(&F);
+        return OnStart(Fs, PassName);
+    }
+
+    inline Finisher OnStart(LazyCallGraph::SCC &C, StringRef PassName) {
+        SmallVector<Function *> Fs;
+        for (LazyCallGraph::Node &N: C)
+            Fs.push_back(&N.getFunction());
+        return OnStart(Fs, PassName);
     }
 
     /*
      * Track instruction manipulations
+     *
+     * The hooks do nothing out of a pass entry, e.g., in a utility of another
+     * file called by a pass that is not instrumented
      */
-    void OnCreate(Value *V, unsigned SrcLine, StringRef VarName) {
+    inline void OnCreate(Value *V, unsigned SrcLine, StringRef VarName) {
+        if (!DLM)
+            return ;
         Instruction *I = dyn_cast<Instruction>(V);
         if (I == nullptr)
             return ;
@@ -373,19 +487,32 @@ namespace hook {
         DLM->InstToStat[HashOfInst] = new DLStat(InstKind::Create, SrcLine, VarName);
         DLM->InstToStat[HashOfInst]->addEvent(Event::Create, SrcLine);
 
-        if (I->isTerminator()) {
-            hash_code HashOfBB = hash_value(I->getParent());
-            if (!DLM->BBToOldTerm.contains(HashOfBB)) {
-                DLM->BBToNewTerm[HashOfBB] = {HashOfInst, VarName};
-            } else {
-                DLM->InstToStat[HashOfInst]->addSrc(DLM->BBToOldTerm[HashOfBB].first, DLM->BBToOldTerm[HashOfBB].second);
-                DLM->InstToStat[HashOfInst]->addEvent(Event::UseReplace, 0);
-                DLM->BBToOldTerm.erase(HashOfBB);
-            }
-        }
+        // A detached terminator is paired when it is inserted
+        if (I->isTerminator() && I->getParent())
+            DLM->onNewTerminator(I, VarName);
+    }
+
+    /// The instruction created by an IRBuilder takes the current location of the
+    /// builder, e.g., the one of the instruction given to `SetInsertPoint`
+    inline void OnBuilderCreate(Value *V, unsigned SrcLine, StringRef VarName, StringRef BuilderName) {
+        if (!DLM)
+            return ;
+        // The builder may fold the instruction into a constant
+        Instruction *I = dyn_cast<Instruction>(V);
+        if (I == nullptr)
+            return ;
+
+        OnCreate(I, SrcLine, VarName);
+        DLStat *Stat = DLM->InstToStat[hash_value(I)];
+        Stat->setBuilder(BuilderName);
+        Stat->addEvent(Event::BuilderCreate, SrcLine);
+        if (I->getDebugLoc())
+            Stat->setDebugLocUpdate(UpdateKind::Preserve, SrcLine);
     }
 
-    void OnMove(Value *V, unsigned SrcLine, StringRef VarName) {
+    inline void OnMove(Value *V, unsigned SrcLine, StringRef VarName) {
+        if (!DLM)
+            return ;
         if (Instruction *I = dyn_cast<Instruction>(V)) {
             DLM->InstToStat[hash_value(I)] = new DLStat(InstKind::Move, SrcLine, VarName);
             DLM->InstToStat[hash_value(I)]->addSrc(hash_value(I), VarName);
@@ -393,7 +520,23 @@ namespace hook {
         }
     }
 
-    void OnClone(Value *NV, Value *OV, unsigned SrcLine, StringRef VarName, StringRef OldValName) {
+    /// A tracked instruction lands in a block, e.g., by `insertBefore`, where it
+    /// may take the place of the terminator
+    inline void OnInsert(Value *V, unsigned SrcLine, StringRef VarName) {
+        if (!DLM)
+            return ;
+        Instruction *I = dyn_cast<Instruction>(V);
+        if (I == nullptr || !DLM->InstToStat.contains(hash_value(I)))
+            return ;
+
+        DLM->InstToStat[hash_value(I)]->addEvent(Event::Insert, SrcLine);
+        if (I->isTerminator() && I->getParent())
+            DLM->onNewTerminator(I, VarName);
+    }
+
+    inline void OnClone(Value *NV, Value *OV, unsigned SrcLine, StringRef VarName, StringRef OldValName) {
+        if (!DLM)
+            return ;
         Instruction *NI = dyn_cast<Instruction>(NV);
         Instruction *OI = dyn_cast<Instruction>(OV);
 
@@ -404,7 +547,9 @@ namespace hook {
         DLM->InstToStat[hash_value(NI)]->addEvent(Event::Clone, SrcLine);
     }
 
-    void OnUseReplace(Value *From, Value *To, unsigned SrcLine, StringRef VarName, StringRef OldValName) {
+    inline void OnUseReplace(Value *From, Value *To, unsigned SrcLine, StringRef VarName, StringRef OldValName) {
+        if (!DLM)
+            return ;
         Instruction *FromI = dyn_cast<Instruction>(From);
         Instruction *ToI = dyn_cast<Instruction>(To);
 
@@ -416,7 +561,9 @@ namespace hook {
         }
     }
 
-    void OnRemove(Value *DV, unsigned SrcLine, StringRef VarName) {
+    inline void OnRemove(Value *DV, unsigned SrcLine, StringRef VarName) {
+        if (!DLM)
+            return ;
         Instruction *DI = dyn_cast<Instruction>(DV);
         if (DI == nullptr)
             return ;
@@ -436,10 +583,103 @@ namespace hook {
         }
     }
 
+    /*
+     * Track the helpers of Transforms/Utils doing several manipulations in one call,
+     * e.g., `ReplaceInstWithInst` replacing the uses of an instruction and erasing it
+     */
+
+    /// The source of a replacement, captured before the helper may erase it
+    struct Replacement {
+        Instruction *Src = nullptr;
+        StringRef SrcName;
+        DebugLoc SrcLoc;
+        BasicBlock *Parent = nullptr;
+        Instruction *Prev = nullptr;
+    };
+
+    inline Replacement OnReplaceStart(Value *SV, StringRef SrcName) {
+        Instruction *SI = DLM ? dyn_cast<Instruction>(SV) : nullptr;
+        if (SI == nullptr)
+            return {};
+        return { SI, SrcName, SI->getDebugLoc(), SI->getParent(), SI->getPrevNode() };
+    }
+
+    /// The destination is the value given, or the instruction taking the place of
+    /// the source if none, e.g., the `unreachable` put by `changeToUnreachable`
+    inline void OnReplace(const Replacement &R, Value *DV, unsigned SrcLine, StringRef VarName) {
+        if (!DLM || R.Src == nullptr)
+            return ;
+        Instruction *DI = nullptr;
+        if (DV)
+            DI = dyn_cast<Instruction>(DV);
+        else if (R.Parent)
+            DI = R.Prev ? R.Prev->getNextNode() : &R.Parent->front();
+        if (DI == nullptr || DI == R.Src)
+            return ;
+
+        hash_code HashOfInst = hash_value(DI);
+        if (!DLM->InstToStat.contains(HashOfInst)) {
+            DLM->InstToStat[HashOfInst] = new DLStat(InstKind::Create, SrcLine, VarName);
+            DLM->InstToStat[HashOfInst]->addEvent(Event::Create, SrcLine);
+        }
+        DLStat *Stat = DLM->InstToStat[HashOfInst];
+        Stat->addSrc(hash_value(R.Src), R.SrcName);
+        Stat->addEvent(Event::Replace, SrcLine);
+        // The helpers copy the location of the source unless the destination has one
+        if (R.SrcLoc && DI->getDebugLoc() == R.SrcLoc)
+            Stat->setDebugLocUpdate(UpdateKind::Preserve, SrcLine);
+    }
+
+    /// The instructions of the block spliced from, captured before `BasicBlock::splice`
+    struct Splice {
+        BasicBlock *From = nullptr;
+        SmallVector<Instruction *> Insts;
+    };
+
+    inline Splice OnSpliceStart(BasicBlock *From) {
+        Splice S;
+        if (!DLM || From == nullptr)
+            return S;
+        S.From = From;
+        for (Instruction &I: *From)
+            S.Insts.push_back(&I);
+        return S;
+    }
+
+    /// The instructions spliced out of the block are moved, keeping their locations
+    inline void OnSplice(const Splice &S, unsigned SrcLine, StringRef VarName) {
+        for (Instruction *I: S.Insts)
+            if (I->getParent() != S.From)
+                OnMove(I, SrcLine, VarName);
+    }
+
+    /*
+     * Track the instructions created or cloned in an expression, which they return
+     */
+    template <typename T>
+    T *Created(T *V, unsigned SrcLine, StringRef VarName) {
+        OnCreate(V, SrcLine, VarName);
+        return V;
+    }
+
+    template <typename T>
+    T *BuilderCreated(T *V, unsigned SrcLine, StringRef VarName, StringRef BuilderName) {
+        OnBuilderCreate(V, SrcLine, VarName, BuilderName);
+        return V;
+    }
+
+    template <typename T>
+    T *Cloned(T *NV, Value *OV, unsigned SrcLine, StringRef VarName, StringRef OldValName) {
+        OnClone(NV, OV, SrcLine, VarName, OldValName);
+        return NV;
+    }
+
     /*
      * Track debug location updates
      */
-    void OnPreserve(Value *DV, unsigned SrcLine) {
+    inline void OnPreserve(Value *DV, unsigned SrcLine) {
+        if (!DLM)
+            return ;
         Instruction *DI = dyn_cast<Instruction>(DV);
         if (DI == nullptr)
             return;
@@ -447,7 +687,9 @@ namespace hook {
         DLM->InstToStat[hash_value(DI)]->setDebugLocUpdate(UpdateKind::Preserve, SrcLine);
     }
 
-    void OnMerge(Value *DV, unsigned SrcLine) {
+    inline void OnMerge(Value *DV, unsigned SrcLine) {
+        if (!DLM)
+            return ;
         Instruction *DI = dyn_cast<Instruction>(DV);
         if (DI == nullptr)
             return;
@@ -455,13 +697,27 @@ namespace hook {
         DLM->InstToStat[hash_value(DI)]->setDebugLocUpdate(UpdateKind::Merge, SrcLine);
     }
 
-    void OnDrop(Value *DV, unsigned SrcLine) {
+    inline void OnDrop(Value *DV, unsigned SrcLine) {
+        if (!DLM)
+            return ;
         Instruction *DI = dyn_cast<Instruction>(DV);
         if (DI == nullptr)
             return;
 
         DLM->InstToStat[hash_value(DI)]->setDebugLocUpdate(UpdateKind::Drop, SrcLine);
     }
+
+    /// `setDebugLoc(Loc)`, whose kind is told from `Loc` by the instrumenter,
+    /// e.g., `Drop` for `DebugLoc()` or `Merge` for `DILocation::getMergedLocation(...)`
+    inline void OnSetDebugLoc(Value *DV, UpdateKind Kind, unsigned SrcLine, StringRef Expr) {
+        if (!DLM)
+            return ;
+        Instruction *DI = dyn_cast<Instruction>(DV);
+        if (DI == nullptr || !DLM->InstToStat.contains(hash_value(DI)))
+            return;
+
+        DLM->InstToStat[hash_value(DI)]->setDebugLocUpdate(Kind, SrcLine, Expr);
+    }
 }
 
 #endif // LLVM_TRANSFORM_UTILS_DL_MONITOR_H
\ No newline at end of file
@@ -8,6 +8,7 @@ pub trait AstNode {
 
     fn to_raw(&self, code: &str) -> String;
     fn to_source(&self, code: &str) -> String;
+    fn to_snippet(&self, code: &str) -> String;
     fn dump_ast(&self);
     fn dump_source(&self, code: &str);
 
@@ -26,24 +27,52 @@ impl<'tree> AstNode for Node<'tree> {
     }
 
     fn to_source(&self, code: &str) -> String {
-        let source: Vec<&str> = (&code[self.start_byte()..self.end_byte()])
+        let source: Vec<&str> = code[self.start_byte()..self.end_byte()]
             .split("\n")
             .map(|s| s.trim())
             .collect();
         source.join(" ")
     }
 
+    /// The first line of the node with the node underlined, in the style of rustc
+    fn to_snippet(&self, code: &str) -> String {
+        let line_start = code[..self.start_byte()]
+            .rfind('\n')
+            .map_or(0, |pos| pos + 1);
+        let line_end = code[self.start_byte()..]
+            .find('\n')
+            .map_or(code.len(), |pos| self.start_byte() + pos);
+        let line = &code[line_start..line_end];
+
+        let row = self.row().to_string();
+        let gutter = " ".repeat(row.len());
+        let indent: String = code[line_start..self.start_byte()]
+            .chars()
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let width = self.end_byte().min(line_end) - self.start_byte();
+
+        format!(
+            "{} {}\n{} {} {}\n{} {} {}{}",
+            gutter,
+            "|".blue().bold(),
+            row.blue().bold(),
+            "|".blue().bold(),
+            line,
+            gutter,
+            "|".blue().bold(),
+            indent,
+            "^".repeat(width.max(1)).red().bold(),
+        )
+    }
+
     fn dump_ast(&self) {
         println!("{}", self.to_sexp());
     }
 
     fn dump_source(&self, code: &str) {
-        println!(
-            "{} ({}): {}",
-            self.start_position().row.to_string().red().bold(),
-            self.kind().green().bold(),
-            self.to_raw(code),
-        );
+        println!("{} ({})", "-->".blue().bold(), self.kind().green().bold());
+        println!("{}", self.to_snippet(code));
     }
 
     fn is_fn_def(&self) -> bool {
@@ -83,4 +112,4 @@ impl<'tree> AstNode for Node<'tree> {
 //     fn into(self) -> &'static str {
 //         self.to_string()
 //     }
-// }
\ No newline at end of file
+// }
@@ -0,0 +1,148 @@
+use std::{collections::BTreeMap, fs, path::Path};
+
+use serde::Deserialize;
+
+use crate::r#match::FnKind;
+
+/// The catalog embedded in the binary, used unless `catalog` is configured
+pub const DEFAULT_CATALOG: &str = include_str!("../catalog/api.toml");
+const DEFAULT_SOURCE: &str = "<embedded>";
+
+/// The API names matched for each kind of event
+pub type Table = BTreeMap<FnKind, Vec<String>>;
+
+/// A catalog file, i.e., the APIs of the oldest LLVM supported and the lists
+/// replaced by each later LLVM version
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CatalogFile {
+    base: Table,
+    #[serde(default)]
+    llvm: BTreeMap<String, Table>,
+}
+
+/// The effective API catalog for one LLVM version
+#[derive(Clone)]
+pub struct Catalog {
+    /// The file it is loaded from, or the embedded one
+    pub source: String,
+    /// The LLVM version selected, or the latest one of the catalog if none
+    pub version: Option<u32>,
+    /// The profiles applied on the base, in order
+    pub profiles: Vec<u32>,
+    table: Table,
+}
+
+impl Catalog {
+    /// Load the catalog from the JSON or TOML file, or the embedded one, applying
+    /// the profiles up to `version`
+    pub fn load(path: Option<&Path>, version: Option<u32>) -> Result<Self, String> {
+        let (source, content) = match path {
+            Some(path) => (
+                path.display().to_string(),
+                fs::read_to_string(path)
+                    .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?,
+            ),
+            None => (DEFAULT_SOURCE.to_string(), DEFAULT_CATALOG.to_string()),
+        };
+        let file: CatalogFile = if path.is_some_and(|p| p.extension().is_some_and(|e| e == "json"))
+        {
+            serde_json::from_str(&content)
+                .map_err(|e| format!("Failed to parse {}: {}", source, e))?
+        } else {
+            toml::from_str(&content).map_err(|e| format!("Failed to parse {}: {}", source, e))?
+        };
+
+        let mut profiles = BTreeMap::new();
+        for (key, table) in file.llvm {
+            let major = key.parse::<u32>().map_err(|_| {
+                format!(
+                    "Invalid LLVM version `{}` in {}, expected a major version like `18`",
+                    key, source
+                )
+            })?;
+            profiles.insert(major, table);
+        }
+
+        let mut table = file.base;
+        let mut applied = vec![];
+        for (major, profile) in profiles {
+            if version.is_some_and(|version| major > version) {
+                break;
+            }
+            table.extend(profile);
+            applied.push(major);
+        }
+
+        Ok(Self {
+            source,
+            version,
+            profiles: applied,
+            table,
+        })
+    }
+
+    /// The names matched for the kind, in the order of the catalog
+    pub fn names(&self, kind: FnKind) -> &[String] {
+        self.table.get(&kind).map_or(&[], Vec::as_slice)
+    }
+
+    /// The effective table, which loads back as a catalog of its own
+    pub fn to_toml(&self) -> String {
+        let file = BTreeMap::from([("base", &self.table)]);
+        toml::to_string_pretty(&file).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(catalog: &Catalog, kind: FnKind) -> Vec<&str> {
+        catalog.names(kind).iter().map(String::as_str).collect()
+    }
+
+    #[test]
+    fn profiles_apply_up_to_the_version() {
+        let llvm15 = Catalog::load(None, Some(15)).unwrap();
+        assert!(llvm15.profiles.is_empty());
+        assert!(llvm15.names(FnKind::Splice).is_empty());
+        assert_eq!(names(&llvm15, FnKind::Move), ["moveBefore", "moveAfter"]);
+
+        let llvm17 = Catalog::load(None, Some(17)).unwrap();
+        assert_eq!(llvm17.profiles, [16]);
+        assert_eq!(names(&llvm17, FnKind::Splice), ["splice"]);
+        assert_eq!(names(&llvm17, FnKind::Move), ["moveBefore", "moveAfter"]);
+
+        let latest = Catalog::load(None, None).unwrap();
+        assert_eq!(latest.profiles, [16, 18]);
+        assert_eq!(names(&latest, FnKind::Splice), ["splice"]);
+        assert_eq!(
+            names(&latest, FnKind::Move),
+            ["moveBefore", "moveBeforePreserving", "moveAfter"]
+        );
+        /* The lists left alone by the profiles are the base ones */
+        assert_eq!(names(&latest, FnKind::Remove), ["eraseFromParent"]);
+    }
+
+    #[test]
+    fn later_profiles_replace_the_earlier_lists() {
+        let path =
+            std::env::temp_dir().join(format!("passinst-catalog-{}.toml", std::process::id()));
+        fs::write(
+            &path,
+            "[base]\nMove = [\"moveBefore\"]\n\n\
+             [llvm.18]\nMove = [\"moveAfter\"]\n\n\
+             [llvm.9]\nMove = [\"moveBefore\", \"moveAfter\"]\n",
+        )
+        .unwrap();
+        let versions = [None, Some(8), Some(10)].map(|version| Catalog::load(Some(&path), version));
+        fs::remove_file(&path).unwrap();
+
+        let [latest, llvm8, llvm10] = versions.map(Result::unwrap);
+        assert_eq!(latest.profiles, [9, 18]);
+        assert_eq!(names(&latest, FnKind::Move), ["moveAfter"]);
+        assert_eq!(names(&llvm8, FnKind::Move), ["moveBefore"]);
+        assert_eq!(names(&llvm10, FnKind::Move), ["moveBefore", "moveAfter"]);
+    }
+}
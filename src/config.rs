@@ -0,0 +1,164 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+
+pub const CONFIG_FILE: &str = "config.json";
+pub const TOML_CONFIG_FILE: &str = "metaloc.toml";
+
+pub const LLVM_ENV: &str = "METALOC_LLVM";
+pub const OPT_ENV: &str = "METALOC_OPT";
+
+/// The directories an LLVM root must contain for instrumentation
+const LLVM_LAYOUT: [&str; 2] = ["include/llvm/Transforms/Utils", "lib/Transforms"];
+
+/// Paths shared by all the tasks.
+///
+/// They are loaded from `config.json`, overridden by `metaloc.toml` if present,
+/// or from the file given by `--config` alone, and then by `METALOC_LLVM`/`METALOC_OPT`.
+#[derive(Deserialize, Default)]
+pub struct Config {
+    /// Path to the root of LLVM, i.e., `llvm-project/llvm`
+    pub llvm: Option<PathBuf>,
+    /// Path to the `opt` binary used for analyzing
+    pub opt: Option<PathBuf>,
+    /// Path to the API catalog replacing the embedded one
+    pub catalog: Option<PathBuf>,
+    /// Major version of the LLVM instrumented, selecting the profiles of the catalog
+    pub llvm_version: Option<u32>,
+}
+
+impl Config {
+    pub fn load(config_path: Option<&Path>) -> Result<Self, String> {
+        let mut config = Config::default();
+
+        match config_path {
+            Some(path) => {
+                if !path.is_file() {
+                    return Err(format!("Config file {} does not exist", path.display()));
+                }
+                config.merge(Config::from_file(path)?);
+            }
+            None => {
+                for path in [CONFIG_FILE, TOML_CONFIG_FILE].map(Path::new) {
+                    if path.is_file() {
+                        config.merge(Config::from_file(path)?);
+                    }
+                }
+            }
+        }
+
+        config.merge(Config {
+            llvm: env::var_os(LLVM_ENV).map(PathBuf::from),
+            opt: env::var_os(OPT_ENV).map(PathBuf::from),
+            ..Config::default()
+        });
+
+        Ok(Self {
+            llvm: config.llvm.map(|p| expand_user(&p)),
+            opt: config.opt.map(|p| expand_user(&p)),
+            catalog: config.catalog.map(|p| expand_user(&p)),
+            llvm_version: config.llvm_version,
+        })
+    }
+
+    /// Parse a JSON or TOML config file according to its extension
+    fn from_file(path: &Path) -> Result<Self, String> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        if path.extension().is_some_and(|ext| ext == "toml") {
+            toml::from_str(&content)
+                .map_err(|e| format!("Failed to parse {}: {}", path.display(), e))
+        } else {
+            serde_json::from_str(&content)
+                .map_err(|e| format!("Failed to parse {}: {}", path.display(), e))
+        }
+    }
+
+    fn merge(&mut self, other: Config) {
+        if other.llvm.is_some() {
+            self.llvm = other.llvm;
+        }
+        if other.opt.is_some() {
+            self.opt = other.opt;
+        }
+        if other.catalog.is_some() {
+            self.catalog = other.catalog;
+        }
+        if other.llvm_version.is_some() {
+            self.llvm_version = other.llvm_version;
+        }
+    }
+
+    /// The LLVM root, checked to have the layout of `llvm-project/llvm`
+    pub fn llvm_root(&self) -> Result<&Path, String> {
+        let llvm = self.llvm.as_deref().ok_or(format!(
+            "The LLVM root is not configured, set `llvm` in {} or {}, or {}",
+            CONFIG_FILE, TOML_CONFIG_FILE, LLVM_ENV
+        ))?;
+
+        if !llvm.is_dir() {
+            return Err(format!(
+                "LLVM path {} does not exist, check `llvm` in the config",
+                llvm.display()
+            ));
+        }
+
+        if let Some(missing) = LLVM_LAYOUT.iter().find(|dir| !llvm.join(dir).is_dir()) {
+            let hint = if LLVM_LAYOUT
+                .iter()
+                .all(|dir| llvm.join("llvm").join(dir).is_dir())
+            {
+                format!("set `llvm` to {} instead", llvm.join("llvm").display())
+            } else {
+                "`llvm` should point to the `llvm` directory of the LLVM project".to_string()
+            };
+            return Err(format!(
+                "LLVM path {} does not contain {}, {}",
+                llvm.display(),
+                missing,
+                hint
+            ));
+        }
+
+        Ok(llvm)
+    }
+
+    /// The `opt` binary, checked to exist
+    pub fn opt_binary(&self) -> Result<&Path, String> {
+        let opt = self.opt.as_deref().ok_or(format!(
+            "The opt binary is not configured, set `opt` in {} or {}, or {}",
+            CONFIG_FILE, TOML_CONFIG_FILE, OPT_ENV
+        ))?;
+
+        if !opt.is_file() {
+            return Err(format!(
+                "LLVM opt path {} does not exist, check `opt` in the config or build it with `ninja opt`",
+                opt.display()
+            ));
+        }
+
+        Ok(opt)
+    }
+}
+
+/// `llvm/include/llvm/Transforms/Utils/DLMonitor.h`
+pub fn library_path(llvm: &Path) -> PathBuf {
+    llvm.join("include")
+        .join("llvm")
+        .join("Transforms")
+        .join("Utils")
+        .join("DLMonitor.h")
+}
+
+/// Expand the leading `~` like `os.path.expanduser`
+fn expand_user(path: &Path) -> PathBuf {
+    if let Ok(rest) = path.strip_prefix("~") {
+        if let Some(home) = env::var_os("HOME") {
+            return PathBuf::from(home).join(rest);
+        }
+    }
+    path.to_path_buf()
+}
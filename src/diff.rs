@@ -0,0 +1,57 @@
+use colored::Colorize;
+use similar::{ChangeTag, TextDiff};
+
+const CONTEXT_LINES: usize = 3;
+
+/// Print the instrumentation of `filename` as a colored unified diff,
+/// emphasizing the inserted hooks inside the changed lines
+pub fn print_unified_diff(filename: &str, original: &str, instrumented: &str) {
+    let diff = TextDiff::from_lines(original, instrumented);
+    if diff.ratio() == 1.0 {
+        return;
+    }
+
+    println!("{}", format!("--- a/{}", filename).bold());
+    println!("{}", format!("+++ b/{}", filename).bold());
+
+    for group in diff.grouped_ops(CONTEXT_LINES) {
+        let (first, last) = (group.first().unwrap(), group.last().unwrap());
+        let old_range = first.old_range().start..last.old_range().end;
+        let new_range = first.new_range().start..last.new_range().end;
+        println!(
+            "{}",
+            format!(
+                "@@ -{},{} +{},{} @@",
+                old_range.start + 1,
+                old_range.len(),
+                new_range.start + 1,
+                new_range.len()
+            )
+            .cyan()
+        );
+
+        for op in &group {
+            for change in diff.iter_inline_changes(op) {
+                let (sign, color): (&str, fn(&str) -> colored::ColoredString) = match change.tag() {
+                    ChangeTag::Delete => ("-", |s| s.red()),
+                    ChangeTag::Insert => ("+", |s| s.green()),
+                    ChangeTag::Equal => (" ", |s| s.normal()),
+                };
+
+                let mut line = color(sign).to_string();
+                for (emphasized, value) in change.iter_strings_lossy() {
+                    let value = value.trim_end_matches('\n');
+                    if emphasized {
+                        line += &color(value).bold().underline().to_string();
+                    } else {
+                        line += &color(value).to_string();
+                    }
+                }
+                println!("{}", line);
+                if change.missing_newline() {
+                    println!("\\ No newline at end of file");
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,148 @@
+use std::ops::Range;
+
+/// A piece of the code replacing an edited range
+#[derive(PartialEq, Debug)]
+pub enum Segment {
+    Text(String),
+    /// The original code in the range, with the edits nested in it applied
+    Source(Range<usize>),
+}
+
+/// Replace a range of the code, where an empty range is an insertion
+#[derive(PartialEq, Debug)]
+pub struct Edit {
+    pub range: Range<usize>,
+    pub content: Vec<Segment>,
+    /// Range of the site the edit is made for, to locate conflicts
+    pub origin: Range<usize>,
+}
+
+impl Edit {
+    pub fn insert(content: String, pos: usize, origin: Range<usize>) -> Self {
+        Self {
+            range: pos..pos,
+            content: vec![Segment::Text(content)],
+            origin,
+        }
+    }
+
+    /// Surround the range, keeping the edits in it
+    pub fn wrap(prefix: String, range: Range<usize>, suffix: String, origin: Range<usize>) -> Self {
+        Self {
+            range: range.clone(),
+            content: vec![
+                Segment::Text(prefix),
+                Segment::Source(range),
+                Segment::Text(suffix),
+            ],
+            origin,
+        }
+    }
+
+    pub fn replace(content: Vec<Segment>, range: Range<usize>, origin: Range<usize>) -> Self {
+        Self {
+            range,
+            content,
+            origin,
+        }
+    }
+
+    /// Insertions at either end of the edit go outside it
+    fn contains(&self, other: &Edit) -> bool {
+        if other.range.is_empty() {
+            self.range.start < other.range.start && other.range.start < self.range.end
+        } else {
+            self.range.start <= other.range.start && other.range.end <= self.range.end
+        }
+    }
+}
+
+/// Two edits that cannot be composed
+pub struct Conflict {
+    /// Origin of the edit that cannot be applied
+    pub origin: Range<usize>,
+    /// Origin of the edit it conflicts with
+    pub other: Range<usize>,
+    /// How the edit conflicts with the other one
+    pub reason: &'static str,
+}
+
+/// Apply the edits to the code as a tree, where an edit contained in another one
+/// is applied to the code the outer one keeps.
+///
+/// Edits of the same range nest in the order they are given, and insertions at
+/// the start of an edit go before it.
+pub fn apply(code: &str, edits: &[Edit]) -> Result<String, Conflict> {
+    let mut sorted: Vec<&Edit> = edits.iter().collect();
+    sorted.sort_by_key(|e| {
+        (
+            e.range.start,
+            !e.range.is_empty(),
+            std::cmp::Reverse(e.range.end),
+        )
+    });
+    render(code, 0..code.len(), &sorted)
+}
+
+/// Render the code in `range` with the `edits` in it, sorted
+fn render(code: &str, range: Range<usize>, edits: &[&Edit]) -> Result<String, Conflict> {
+    let mut rendered = String::new();
+    let mut pos = range.start;
+    let mut prev: Option<&Edit> = None;
+    let mut i = 0;
+    while i < edits.len() {
+        let edit = edits[i];
+        if edit.range.start < pos {
+            return Err(Conflict {
+                origin: edit.origin.clone(),
+                other: prev.unwrap().origin.clone(),
+                reason: "overlaps the rewrite",
+            });
+        }
+
+        /* The edits after it which it contains */
+        let mut end = i + 1;
+        while end < edits.len() && edit.contains(edits[end]) {
+            end += 1;
+        }
+        let nested = &edits[i + 1..end];
+
+        rendered += &code[pos..edit.range.start];
+        rendered += &render_edit(code, edit, nested)?;
+        pos = edit.range.end;
+        prev = Some(edit);
+        i = end;
+    }
+    rendered += &code[pos..range.end];
+    Ok(rendered)
+}
+
+fn render_edit(code: &str, edit: &Edit, nested: &[&Edit]) -> Result<String, Conflict> {
+    let mut placed = vec![false; nested.len()];
+    let mut rendered = String::new();
+    for segment in &edit.content {
+        match segment {
+            Segment::Text(text) => rendered += text,
+            Segment::Source(hole) => {
+                let mut inner = vec![];
+                for (j, e) in nested.iter().enumerate() {
+                    if !placed[j] && hole.start <= e.range.start && e.range.end <= hole.end {
+                        placed[j] = true;
+                        inner.push(*e);
+                    }
+                }
+                rendered += &render(code, hole.clone(), &inner)?;
+            }
+        }
+    }
+
+    /* Nested in the code the edit drops */
+    if let Some(j) = placed.iter().position(|placed| !placed) {
+        return Err(Conflict {
+            origin: nested[j].origin.clone(),
+            other: edit.origin.clone(),
+            reason: "lies in the code replaced by the rewrite",
+        });
+    }
+    Ok(rendered)
+}
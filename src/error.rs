@@ -0,0 +1,123 @@
+use std::fmt;
+
+use colored::Colorize;
+use serde::Serialize;
+use tree_sitter::Node;
+
+use crate::ast::AstNode;
+
+/// Process exit codes, so that scripts can tell the outcomes apart
+#[derive(Clone, Copy, PartialEq)]
+pub enum Status {
+    Success = 0,
+    /// Some file failed to be instrumented
+    Failed = 1,
+    /// No file or site to instrument, or no hook to strip
+    NothingToInstrument = 3,
+    /// The configuration or the arguments are invalid
+    Invalid = 4,
+}
+
+impl Status {
+    pub fn exit(self) -> ! {
+        std::process::exit(self as i32)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+/// Where a diagnostic points to in a source file
+#[derive(Serialize)]
+pub struct Span {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    #[serde(skip)]
+    snippet: String,
+}
+
+impl Span {
+    pub fn of(node: &Node, file: &str, code: &str) -> Self {
+        Self {
+            file: file.to_owned(),
+            line: node.row(),
+            column: node.start_position().column + 1,
+            snippet: node.to_snippet(code),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub span: Option<Span>,
+}
+
+impl Diagnostic {
+    pub fn error(message: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Error,
+            message: message.into(),
+            span: None,
+        }
+    }
+
+    pub fn warning(message: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Warning,
+            message: message.into(),
+            span: None,
+        }
+    }
+
+    pub fn at(mut self, node: &Node, file: &str, code: &str) -> Self {
+        self.span = Some(Span::of(node, file, code));
+        self
+    }
+
+    /// Print the diagnostic to stderr
+    pub fn emit(&self) {
+        eprintln!("{}", self);
+    }
+}
+
+/// Rendered like rustc:
+///
+/// ```text
+/// error: unsupported pass target type `Module`
+///   --> Foo.cpp:42:27
+///    |
+/// 42 | PreservedAnalyses FooPass::run(Module &M, ModuleAnalysisManager &AM) {
+///    |                                ^^^^^^
+/// ```
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Warning => "warning".yellow().bold(),
+            Severity::Error => "error".red().bold(),
+        };
+        write!(f, "{}{} {}", severity, ":".bold(), self.message.bold())?;
+
+        if let Some(span) = &self.span {
+            let gutter = " ".repeat(span.line.to_string().len());
+            write!(
+                f,
+                "\n{}{} {}:{}:{}\n{}",
+                gutter,
+                "-->".blue().bold(),
+                span.file,
+                span.line,
+                span.column,
+                span.snippet
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -1,21 +1,86 @@
+use crate::r#match::UpdateKind;
+
 pub const HEADER_INCLUDE: &str = "#include \"llvm/Transforms/Utils/DLMonitor.h\"\n";
 
+/// The namespace of the hooks, which the instrumented code calls into
+pub const NAMESPACE: &str = "hook::";
+
+/// The monitor library included by the instrumented passes
+pub const LIBRARY: &str = include_str!("../library/DLMonitor.h");
+
+/// The hook starting the analysis in the pass entries
+pub const ON_START: &str = "hook::OnStart";
+
 /// Hook for OnStart
 pub fn on_start(pass_target: &str, pass_name: &str) -> String {
-    format!("hook::OnStart({}, \"{}\")", pass_target, pass_name)
+    format!("{}({}, \"{}\")", ON_START, pass_target, escape(pass_name))
 }
 
-/// Hook for OnFinish
-pub fn on_finish() -> String {
-    format!("hook::OnFinish()")
+/// Identity hook tracking a creation in place, as the code around the expression
+pub fn created(line: usize, var_name: &str) -> (String, String) {
+    (
+        "hook::Created(".to_string(),
+        format!(", {}, \"{}\")", line, escape(var_name)),
+    )
+}
+
+/// Identity hook tracking a creation by the builder in place, as the code around the expression
+pub fn builder_created(line: usize, var_name: &str, builder: &str) -> (String, String) {
+    (
+        "hook::BuilderCreated(".to_string(),
+        format!(
+            ", {}, \"{}\", \"{}\")",
+            line,
+            escape(var_name),
+            escape(builder)
+        ),
+    )
+}
+
+/// Identity hook tracking a clone in place, as the code around the expression
+pub fn cloned(old_val: &str, line: usize, var_name: &str, old_val_name: &str) -> (String, String) {
+    (
+        "hook::Cloned(".to_string(),
+        format!(
+            ", {}, {}, \"{}\", \"{}\")",
+            old_val,
+            line,
+            escape(var_name),
+            escape(old_val_name)
+        ),
+    )
 }
 
 pub fn on_create(val: &str, line: usize, var_name: &str) -> String {
-    format!("hook::OnCreate({}, {}, \"{}\")", val, line, var_name)
+    format!(
+        "hook::OnCreate({}, {}, \"{}\")",
+        val,
+        line,
+        escape(var_name)
+    )
+}
+
+pub fn on_builder_create(val: &str, line: usize, var_name: &str, builder: &str) -> String {
+    format!(
+        "hook::OnBuilderCreate({}, {}, \"{}\", \"{}\")",
+        val,
+        line,
+        escape(var_name),
+        escape(builder)
+    )
 }
 
 pub fn on_move(val: &str, line: usize, var_name: &str) -> String {
-    format!("hook::OnMove({}, {}, \"{}\")", val, line, var_name)
+    format!("hook::OnMove({}, {}, \"{}\")", val, line, escape(var_name))
+}
+
+pub fn on_insert(val: &str, line: usize, var_name: &str) -> String {
+    format!(
+        "hook::OnInsert({}, {}, \"{}\")",
+        val,
+        line,
+        escape(var_name)
+    )
 }
 
 pub fn on_clone(
@@ -27,7 +92,11 @@ pub fn on_clone(
 ) -> String {
     format!(
         "hook::OnClone({}, {}, {}, \"{}\", \"{}\")",
-        new_val, old_val, line, var_name, old_var_name
+        new_val,
+        old_val,
+        line,
+        escape(var_name),
+        escape(old_var_name)
     )
 }
 
@@ -40,12 +109,54 @@ pub fn on_use_replace(
 ) -> String {
     format!(
         "hook::OnUseReplace({}, {}, {}, \"{}\", \"{}\")",
-        from_val, to_val, line, var_name, old_var_name
+        from_val,
+        to_val,
+        line,
+        escape(var_name),
+        escape(old_var_name)
+    )
+}
+
+/// Captures the source of a replacement helper before the call, which may erase it
+pub fn on_replace_start(src_val: &str, src_name: &str) -> String {
+    format!(
+        "hook::OnReplaceStart({}, \"{}\")",
+        src_val,
+        escape(src_name)
+    )
+}
+
+pub fn on_replace(replacement: &str, dst_val: &str, line: usize, var_name: &str) -> String {
+    format!(
+        "hook::OnReplace({}, {}, {}, \"{}\")",
+        replacement,
+        dst_val,
+        line,
+        escape(var_name)
+    )
+}
+
+/// Captures the instructions of the block spliced from before the call
+pub fn on_splice_start(from_block: &str) -> String {
+    format!("hook::OnSpliceStart({})", from_block)
+}
+
+pub fn on_splice(splice: &str, line: usize, var_name: &str) -> String {
+    format!(
+        "hook::OnSplice({}, {}, \"{}\")",
+        splice,
+        line,
+        escape(var_name)
     )
 }
 
 pub fn on_remove(val: &str, line: usize, var_name: &str) -> String {
-    format!("hook::OnRemove({}, {}, \"{}\")", val, line, var_name)
+    format!(
+        "hook::OnRemove({}, {}, \"{}\")",
+        val,
+        line,
+        escape(var_name)
+    )
 }
 
 pub fn on_preserve(val: &str, line: usize) -> String {
@@ -59,3 +170,24 @@ pub fn on_merge(val: &str, line: usize) -> String {
 pub fn on_drop(val: &str, line: usize) -> String {
     format!("hook::OnDrop({}, {})", val, line)
 }
+
+/// `setDebugLoc(Loc)` with the kind of `Loc` and its code, e.g., `::UpdateKind::Drop`
+/// and `DebugLoc()`
+pub fn on_set_debug_loc(val: &str, kind: UpdateKind, line: usize, loc: &str) -> String {
+    format!(
+        "hook::OnSetDebugLoc({}, ::UpdateKind::{:?}, {}, \"{}\")",
+        val,
+        kind,
+        line,
+        escape(loc)
+    )
+}
+
+/// The code or name in a string literal, on one line
+fn escape(code: &str) -> String {
+    code.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+}
@@ -1,58 +1,47 @@
-use std::collections::HashSet;
+use std::{collections::HashSet, ops::Range};
 
-use colored::Colorize;
 use tree_sitter::{Node, Parser};
 
 use crate::{
     ast::AstNode,
+    catalog::Catalog,
+    edit::{self, Conflict, Edit, Segment},
+    error::{Diagnostic, Severity},
     hook,
-    r#match::{FnKind, FnMatch},
+    r#match::{
+        is_instruction_type, is_value_type, location_update, replace_model, FnKind, FnMatch,
+        Operand, PASS_TARGET_TYPES,
+    },
+    report::{Report, Site},
+    symbol::{SymbolTable, CAST_FN},
     visit::{
-        get_children_of_kind, get_fn_identifier, get_parent_of_kind, get_var_name_from_assign,
-        get_var_name_from_decl,
+        get_children_of_kind, get_fn_identifier, get_identifiers, get_parent_of_kind,
+        get_var_name_from_assign, get_var_name_from_declarator,
     },
 };
 
-enum EditKind {
-    Insert,
-    Replace(usize), // End Position
-}
-
-struct Edit {
-    pub content: String,
-    pub start_pos: usize,
-    pub kind: EditKind,
-}
-
-impl Edit {
-    pub fn new_insert(content: String, insert_pos: usize) -> Self {
-        Self {
-            content,
-            start_pos: insert_pos,
-            kind: EditKind::Insert,
-        }
-    }
-
-    pub fn new_replace(content: String, start_pos: usize, end_pos: usize) -> Self {
-        Self {
-            content,
-            start_pos,
-            kind: EditKind::Replace(end_pos),
-        }
-    }
-}
+/// Accessors of the containers, e.g., `BB.front()`, besides the `getXxx` ones
+const ACCESSOR_FN: [&str; 4] = ["front", "back", "begin", "end"];
 
 pub struct Instrumenter {
     parser: Parser,
 
     edits: Vec<Edit>,
-    edit_record: HashSet<String>,
+    /// Number of the pass entries instrumented
+    entries: usize,
+    sites: Vec<Site>,
+    warnings: Vec<Diagnostic>,
+    /// Identifiers in the function being visited, which the temporaries must avoid
+    used_names: HashSet<String>,
+    /// Declared types of the names in the function being visited
+    symbols: SymbolTable,
 
     target_file: String,
+    catalog: Catalog,
 }
 
-impl<'tree> Instrumenter {
-    pub fn new() -> Self {
+impl Instrumenter {
+    pub fn new(catalog: &Catalog) -> Self {
         let mut parser = Parser::new();
         let grammar = tree_sitter_cpp::language();
         parser
@@ -62,482 +51,1818 @@ impl<'tree> Instrumenter {
         Self {
             parser,
             edits: vec![],
-            edit_record: HashSet::new(),
+            entries: 0,
+            sites: vec![],
+            warnings: vec![],
+            used_names: HashSet::new(),
+            symbols: SymbolTable::default(),
             target_file: String::new(),
+            catalog: catalog.clone(),
         }
     }
 
-    pub fn instrument(&mut self, filename: &str, code: &mut String) {
+    /// Instrument the code and report the sites found.
+    ///
+    /// The code is left untouched if the file cannot be instrumented.
+    pub fn instrument(&mut self, filename: &str, code: &mut String) -> Result<Report, Diagnostic> {
         self.target_file = filename.to_owned();
 
-        self.collect_edits(code);
+        if let Err(diag) = self.collect_edits(code) {
+            self.reset();
+            return Err(diag);
+        }
 
-        self.edits.sort_by(|a, b| b.start_pos.cmp(&a.start_pos));
-        for edit in &self.edits {
-            match edit.kind {
-                EditKind::Insert => {
-                    code.insert_str(edit.start_pos, &edit.content);
-                }
-                EditKind::Replace(end_pos) => {
-                    code.replace_range(edit.start_pos..end_pos, &edit.content);
-                }
+        match edit::apply(code, &self.edits) {
+            Ok(instrumented) => *code = instrumented,
+            Err(conflict) => {
+                let diag = self.conflict_error(&conflict, code);
+                self.reset();
+                return Err(diag);
             }
         }
+        let report = Report::new(
+            filename,
+            self.entries,
+            std::mem::take(&mut self.sites),
+            std::mem::take(&mut self.warnings),
+        );
         self.reset();
+        Ok(report)
+    }
+
+    /// Whether the code already includes the monitor or calls a hook,
+    /// i.e., it has been instrumented before
+    pub fn is_instrumented(&mut self, code: &str) -> bool {
+        let Some(tree) = self.parser.parse(code, None) else {
+            return false;
+        };
+        let root_node = tree.root_node();
+
+        get_children_of_kind(&root_node, "preproc_include")
+            .iter()
+            .any(|include| {
+                include
+                    .to_raw(code)
+                    .starts_with(hook::HEADER_INCLUDE.trim_end())
+            })
+            || get_children_of_kind(&root_node, "call_expression")
+                .iter()
+                .filter_map(|call| call.child_by_field_name("function"))
+                .any(|function| function.to_source(code).starts_with(hook::NAMESPACE))
+    }
+
+    /// Whether the instrumented code starts the analysis, i.e., has a pass entry
+    pub fn has_entry(&mut self, code: &str) -> bool {
+        let Some(tree) = self.parser.parse(code, None) else {
+            return false;
+        };
+        get_children_of_kind(&tree.root_node(), "call_expression")
+            .iter()
+            .filter_map(|call| call.child_by_field_name("function"))
+            .any(|function| function.to_source(code) == hook::ON_START)
     }
 }
 
-impl<'tree> Instrumenter {
+impl Instrumenter {
     fn reset(&mut self) {
         self.edits = vec![];
-        self.edit_record = HashSet::new();
+        self.entries = 0;
+        self.sites = vec![];
+        self.warnings = vec![];
+        self.used_names = HashSet::new();
+        self.symbols = SymbolTable::default();
         self.target_file = String::new();
     }
 
-    fn add_insert_edit(&mut self, content: String, insert_pos: usize) {
-        let edit_hash = insert_pos.to_string() + &content;
-        if !self.edit_record.contains(&edit_hash) {
-            self.edits.push(Edit::new_insert(content, insert_pos));
-            self.edit_record.insert(edit_hash);
+    /// A name for a temporary, which appears nowhere else in the function, i.e.,
+    /// `base` itself or `base` suffixed with the first number free
+    fn fresh_name(&mut self, base: &str) -> String {
+        let mut name = base.to_string();
+        let mut suffix = 0;
+        while self.used_names.contains(&name) {
+            suffix += 1;
+            name = format!("{}{}", base, suffix);
+        }
+        self.used_names.insert(name.clone());
+        name
+    }
+
+    fn add_edit(&mut self, edit: Edit) {
+        if !self.edits.contains(&edit) {
+            self.edits.push(edit);
+        }
+    }
+
+    fn add_insert_edit(&mut self, site: &Node, content: String, insert_pos: usize) {
+        self.add_edit(Edit::insert(content, insert_pos, site.byte_range()));
+    }
+
+    fn add_wrap_edit(&mut self, site: &Node, prefix: String, range: Range<usize>, suffix: String) {
+        self.add_edit(Edit::wrap(prefix, range, suffix, site.byte_range()));
+    }
+
+    fn add_replace_edit(&mut self, site: &Node, content: Vec<Segment>, range: Range<usize>) {
+        self.add_edit(Edit::replace(content, range, site.byte_range()));
+    }
+
+    /// Locate the edits that cannot be composed in the original code
+    fn conflict_error(&mut self, conflict: &Conflict, code: &str) -> Diagnostic {
+        let Some(tree) = self.parser.parse(code, None) else {
+            return Diagnostic::error(format!(
+                "cannot compose the rewrites, one {}",
+                conflict.reason
+            ));
+        };
+        let root_node = tree.root_node();
+        let node_at = |range: &Range<usize>| {
+            root_node
+                .descendant_for_byte_range(range.start, range.end)
+                .unwrap_or(root_node)
+        };
+        let other = node_at(&conflict.other);
+        Diagnostic::error(format!(
+            "cannot compose the rewrite here, which {} for line {}:{}",
+            conflict.reason,
+            other.row(),
+            other.start_position().column + 1
+        ))
+        .at(&node_at(&conflict.origin), &self.target_file, code)
+    }
+
+    fn report_hook(&mut self, kind: FnKind, node: &Node, code: &str, vars: &[&str], hook: &str) {
+        self.sites.push(Site {
+            kind,
+            line: node.row(),
+            column: node.start_position().column + 1,
+            source: node.to_source(code),
+            vars: vars.iter().map(|v| v.to_string()).collect(),
+            hook: Some(hook.to_owned()),
+            skipped: None,
+        });
+    }
+
+    fn report_skip(&mut self, kind: FnKind, node: &Node, code: &str, reason: &str) {
+        self.sites.push(Site {
+            kind,
+            line: node.row(),
+            column: node.start_position().column + 1,
+            source: node.to_source(code),
+            vars: vec![],
+            hook: None,
+            skipped: Some(reason.to_owned()),
+        });
+    }
+
+    fn warn(&mut self, diag: Diagnostic) {
+        self.warnings.push(diag);
+    }
+
+    /// A site that cannot be instrumented does not fail the whole file, but is
+    /// reported as skipped for the reason given
+    fn skip_site(&mut self, kind: FnKind, node: &Node, code: &str, mut diag: Diagnostic) {
+        self.report_skip(kind, node, code, &diag.message);
+        diag.severity = Severity::Warning;
+        diag.message = format!("skipped a site, {}", diag.message);
+        self.warnings.push(diag);
+    }
+
+    fn error_at(&self, node: &Node, code: &str, message: impl Into<String>) -> Diagnostic {
+        Diagnostic::error(message).at(node, &self.target_file, code)
+    }
+
+    fn field<'tree>(
+        &self,
+        node: &Node<'tree>,
+        name: &str,
+        code: &str,
+    ) -> Result<Node<'tree>, Diagnostic> {
+        node.child_by_field_name(name).ok_or_else(|| {
+            self.error_at(
+                node,
+                code,
+                format!("missing `{}` in the {}", name, node.kind()),
+            )
+        })
+    }
+
+    fn child<'tree>(
+        &self,
+        node: &Node<'tree>,
+        i: usize,
+        code: &str,
+    ) -> Result<Node<'tree>, Diagnostic> {
+        node.child(i).ok_or_else(|| {
+            self.error_at(
+                node,
+                code,
+                format!("missing child {} in the {}", i, node.kind()),
+            )
+        })
+    }
+
+    fn var_name_from_decl(&self, declarator: &Node, code: &str) -> Result<String, Diagnostic> {
+        get_var_name_from_declarator(declarator)
+            .map(|var_name| var_name.to_source(code))
+            .ok_or_else(|| self.error_at(declarator, code, "cannot find the declared variable"))
+    }
+
+    /// The variable an expression tracked in place is bound to, if any
+    fn bound_var_name(&self, dest: &Destination, code: &str) -> Result<Option<String>, Diagnostic> {
+        match dest {
+            Destination::Bound(declarator) => self.var_name_from_decl(declarator, code).map(Some),
+            _ => Ok(None),
         }
     }
 
-    fn add_replace_edit(&mut self, content: String, start_pos: usize, end_pos: usize) {
-        let edit_hash = start_pos.to_string() + &content;
-        if !self.edit_record.contains(&edit_hash) {
-            self.edits
-                .push(Edit::new_replace(content, start_pos, end_pos));
-            self.edit_record.insert(edit_hash);
+    fn var_name_from_assign(&self, assign: &Node, code: &str) -> Result<String, Diagnostic> {
+        get_var_name_from_assign(assign)
+            .map(|var_name| var_name.to_source(code))
+            .ok_or_else(|| self.error_at(assign, code, "cannot find the assigned variable"))
+    }
+
+    /// The object a method is called on and a pointer to it, e.g., `I` and `&I`
+    /// for `I.eraseFromParent()`
+    fn called_object(&self, callee: &Node, code: &str) -> Result<(String, String), Diagnostic> {
+        let object = self.field(callee, "argument", code)?.to_source(code);
+        let pointer = match self.child(callee, 1, code)?.to_source(code).as_str() {
+            "->" => object.clone(),
+            _ => format!("&{}", object),
+        };
+        Ok((object, pointer))
+    }
+
+    /// Whether the object a method is called on is an instruction, or a block
+    /// for `splice`, skipping the site if it is not known to be, e.g., `BB` of
+    /// `BasicBlock *BB` for `moveBefore`, or an object of unknown class
+    fn accepts_receiver(
+        &mut self,
+        kind: FnKind,
+        call: &Node,
+        callee: &Node,
+        code: &str,
+    ) -> Result<bool, Diagnostic> {
+        let object = self.field(callee, "argument", code)?;
+        let expected = match kind {
+            FnKind::Splice => "a block",
+            _ => "an instruction",
+        };
+        match self.symbols.type_of(&object, code, &self.catalog) {
+            /* The uses of any value can be replaced */
+            Some(ty)
+                if match kind {
+                    FnKind::Splice => ty == "BasicBlock",
+                    FnKind::UseReplace => is_value_type(&ty),
+                    _ => is_instruction_type(&ty),
+                } =>
+            {
+                Ok(true)
+            }
+            Some(ty) => {
+                let reason = format!(
+                    "`{}` is a `{}`, not {}",
+                    object.to_source(code),
+                    ty,
+                    expected
+                );
+                self.report_skip(kind, call, code, &reason);
+                Ok(false)
+            }
+            None => {
+                self.skip_site(
+                    kind,
+                    call,
+                    code,
+                    Diagnostic::warning(format!(
+                        "receiver class unknown, `{}` may not be {}",
+                        object.to_source(code),
+                        expected
+                    ))
+                    .at(&object, &self.target_file, code),
+                );
+                Ok(false)
+            }
         }
     }
 
-    fn collect_edits(&mut self, code: &str) {
+    fn collect_edits(&mut self, code: &str) -> Result<(), Diagnostic> {
         let tree = self
             .parser
             .parse(code, None)
-            .expect("Failed to parse the code!");
+            .ok_or_else(|| Diagnostic::error(format!("failed to parse {}", self.target_file)))?;
         let root_node = tree.root_node();
 
-        self.visit_header_includes(get_children_of_kind(&root_node, "preproc_include"));
         self.visit_fn_defs(
             get_children_of_kind(&root_node, "function_definition"),
             code,
-        );
+        )?;
+        /* A file without hooks needs no include, and is left untouched */
+        if self.edits.is_empty() {
+            return Ok(());
+        }
+        self.visit_header_includes(
+            &root_node,
+            get_children_of_kind(&root_node, "preproc_include"),
+            code,
+        )
     }
 }
 
-impl<'tree> Instrumenter {
+impl Instrumenter {
     /// Add the edit to include our header file
-    fn visit_header_includes(&mut self, nodes: Vec<Node>) {
-        assert_eq!(nodes.is_empty(), false, "No header files included!");
-        self.add_insert_edit(hook::HEADER_INCLUDE.to_string(), nodes[0].start_byte());
+    fn visit_header_includes(
+        &mut self,
+        root: &Node,
+        nodes: Vec<Node>,
+        code: &str,
+    ) -> Result<(), Diagnostic> {
+        let Some(first_include) = nodes.first() else {
+            return Err(self.error_at(
+                root,
+                code,
+                "no header file included, cannot include the monitor",
+            ));
+        };
+        let include = hook::HEADER_INCLUDE.trim_end();
+        /* Keep the line numbers, taking the blank line before the includes if any */
+        let start = first_include.start_byte();
+        if code[..start].ends_with("\n\n") {
+            self.add_insert_edit(first_include, include.to_string(), start - 1);
+            return Ok(());
+        }
+
+        /* Or another blank line out of the top-level constructs before the code,
+         * or else the start of a line comment, e.g., the license header */
+        let top: Vec<Node> = root.children(&mut root.walk()).collect();
+        let end = top
+            .iter()
+            .find(|node| {
+                node.kind() == "function_definition"
+                    || !get_children_of_kind(node, "function_definition").is_empty()
+            })
+            .map_or(code.len(), |node| node.start_byte());
+        let in_construct = |pos: usize| top.iter().any(|node| node.byte_range().contains(&pos));
+        let blank_line = (0..end).find(|&pos| {
+            code.as_bytes()[pos] == b'\n'
+                && (pos == 0 || code.as_bytes()[pos - 1] == b'\n')
+                && !in_construct(pos)
+        });
+        if let Some(pos) = blank_line {
+            self.add_insert_edit(first_include, include.to_string(), pos);
+            return Ok(());
+        }
+        let line_comment = top
+            .iter()
+            .take_while(|node| node.start_byte() < end)
+            .find(|node| {
+                let text = node.to_raw(code);
+                node.kind() == "comment"
+                    && text.starts_with("//")
+                    && !text.trim_end().ends_with('\\')
+                    && node.start_position().column == 0
+            });
+        match line_comment {
+            Some(comment) => {
+                self.add_insert_edit(first_include, format!("{} ", include), comment.start_byte());
+                Ok(())
+            }
+            None => Err(self.error_at(
+                first_include,
+                code,
+                "no blank line or line comment before the code to include the monitor on",
+            )),
+        }
     }
 
-    fn visit_fn_defs(&mut self, nodes: Vec<Node>, code: &str) {
+    fn visit_fn_defs(&mut self, nodes: Vec<Node>, code: &str) -> Result<(), Diagnostic> {
+        let guarded_helpers = called_by_entries(&nodes, code);
         for fn_def in nodes {
-            if get_children_of_kind(&fn_def, "function_declarator").len() == 0 {
-                println!(
-                    "{} {} {}:\n\t{} {}",
-                    "Warning".yellow().bold(),
-                    "Encounter an function definition without declarator at line".bold(),
-                    fn_def.row(),
-                    "->".blue().bold(),
-                    fn_def.to_source(code)
+            let Some(fn_ident) = get_fn_identifier(&fn_def) else {
+                self.warn(
+                    Diagnostic::warning("function definition without declarator").at(
+                        &fn_def,
+                        &self.target_file,
+                        code,
+                    ),
                 );
                 continue;
-            }
+            };
+            self.used_names = get_identifiers(&fn_def, code);
+            self.symbols = SymbolTable::new(&fn_def, code, &self.catalog);
 
-            let fn_ident = get_fn_identifier(&fn_def);
-            if fn_ident.to_source(code).is_pass_entry() {
+            let fn_name = fn_ident.to_source(code);
+            /* A helper like `runImpl` called by an entry runs under the guard of the entry */
+            let guarded = fn_name.is_entry_helper()
+                && guarded_helpers.contains(fn_name.rsplit("::").next().unwrap_or_default());
+            if fn_name.is_pass_entry() && !guarded {
                 /* Add initialization and clean up */
-                self.collect_init_and_clean_up_edit(&fn_def, code);
-            } else {
-                /* Process all function calls */
-                self.visit_fn_calls(get_children_of_kind(&fn_def, "call_expression"), code);
-                /* Process all object news */
-                self.visit_new_exprs(get_children_of_kind(&fn_def, "new_expression"), code);
+                match self.collect_init_and_clean_up_edit(&fn_def, code) {
+                    Ok(()) => {}
+                    /* Helpers like `runImpl` may run on no IR unit, e.g., `runImpl(DominatorTree &DT)` */
+                    Err(mut diag) if fn_name.is_entry_helper() => {
+                        diag.severity = Severity::Warning;
+                        diag.message += ", instrumenting it as a plain function";
+                        self.warn(diag);
+                    }
+                    Err(diag) => return Err(diag),
+                }
             }
+
+            /* Process all function calls, including the ones in the lambdas */
+            self.visit_fn_calls(get_children_of_kind(&fn_def, "call_expression"), code);
+            /* Process all object news */
+            self.visit_new_exprs(get_children_of_kind(&fn_def, "new_expression"), code);
         }
+        Ok(())
     }
 
-    fn collect_init_and_clean_up_edit(&mut self, pass_entry: &Node, code: &str) {
-        let param_list = pass_entry
-            .child_by_field_name("declarator")
-            .unwrap()
-            .child_by_field_name("parameters")
-            .unwrap();
+    fn collect_init_and_clean_up_edit(
+        &mut self,
+        pass_entry: &Node,
+        code: &str,
+    ) -> Result<(), Diagnostic> {
+        let declarator = self.field(pass_entry, "declarator", code)?;
+        let param_list = self.field(&declarator, "parameters", code)?;
         let params = get_children_of_kind(&param_list, "parameter_declaration");
-        assert!(
-            params.len() >= 1,
-            "The pass entry should have the target parameters!"
-        );
 
-        let pass_target_type = params[0].child_by_field_name("type").unwrap();
-        let pass_target_type_str = pass_target_type.to_source(code);
-
-        let pass_target = match pass_target_type_str.as_str() {
-            "Function" | "Loop" | "LoopNest" => {
-                let target_ref = params[0]
-                    .child_by_field_name("declarator")
-                    .unwrap()
-                    .child(1)
-                    .unwrap();
-                target_ref.to_source(code)
-            }
-            _ => unreachable!(),
+        /* The first parameter of an IR unit, e.g., `Function &F` or `Loop *L` */
+        let Some(pass_target) = params.iter().find_map(|param| pass_target(param, code)) else {
+            return Err(
+                match params.first().and_then(|p| p.child_by_field_name("type")) {
+                    Some(ty) => self.error_at(
+                        &ty,
+                        code,
+                        format!("unsupported pass target type `{}`", ty.to_source(code)),
+                    ),
+                    None => self.error_at(
+                        &param_list,
+                        code,
+                        "the pass entry should have the target parameters",
+                    ),
+                },
+            );
         };
 
-        let fn_body = pass_entry.child_by_field_name("body").unwrap();
-        let init_str = format!("{};\n  ", hook::on_start(&pass_target, &self.target_file),);
-        self.add_insert_edit(init_str, fn_body.child(1).unwrap().start_byte());
+        let fn_body = self.field(pass_entry, "body", code)?;
+
+        /* The guard returned by OnStart finishes the analysis on every exit of the entry,
+         * put right after the brace, which no directive shares the line with */
+        let guard = self.fresh_name("FinishGuard");
+        let init_str = format!(
+            " auto {} = {};",
+            guard,
+            hook::on_start(&pass_target, &self.target_file)
+        );
+        self.add_insert_edit(&fn_body, init_str, fn_body.start_byte() + 1);
+        self.entries += 1;
 
-        let return_stmts = get_children_of_kind(&fn_body, "return_statement");
-        for return_stmt in return_stmts {
-            let insert_str = format!("{{ {}; ", hook::on_finish());
-            self.add_insert_edit(insert_str, return_stmt.start_byte());
-            let insert_str = format!(" }}");
-            self.add_insert_edit(insert_str, return_stmt.end_byte());
+        /* A non-void entry without a return of its own may only exit by a macro or a throw */
+        let is_void = pass_entry
+            .child_by_field_name("type")
+            .is_some_and(|ty| ty.to_source(code) == "void");
+        let has_return = get_children_of_kind(&fn_body, "return_statement")
+            .iter()
+            .any(|ret| get_parent_of_kind(ret, "lambda_expression").is_none());
+        if !is_void && !has_return {
+            self.warn(
+                Diagnostic::warning(
+                    "no return found in the pass entry, the analysis finishes only when it exits",
+                )
+                .at(&declarator, &self.target_file, code),
+            );
         }
+        Ok(())
     }
 
     fn visit_fn_calls(&mut self, nodes: Vec<Node>, code: &str) {
         for call in nodes {
-            let callee = call.child_by_field_name("function").unwrap();
-            let arguments = call.child_by_field_name("arguments").unwrap();
+            if let Err(diag) = self.visit_fn_call(call, code) {
+                self.warn(diag);
+            }
+        }
+    }
 
-            let mut callee_name = callee.to_source(code);
-            match callee.kind() {
-                "field_expression" => {
-                    callee_name = callee.child_by_field_name("field").unwrap().to_source(code);
-                }
-                "qualified_identifier" => {}
-                _ => continue,
-            };
+    fn visit_fn_call(&mut self, call: Node, code: &str) -> Result<(), Diagnostic> {
+        let callee = self.field(&call, "function", code)?;
 
-            match callee_name.get_fn_kind() {
-                Some(FnKind::Create) => {
-                    if let Some(parent_decl) = get_parent_of_kind(&call, "declaration") {
-                        let var_name = get_var_name_from_decl(&parent_decl);
-                        let insert_str = format!(
-                            " {};",
-                            hook::on_create(
-                                &var_name.to_source(code),
-                                parent_decl.row(),
-                                &var_name.to_source(code)
-                            )
-                        );
-                        self.add_insert_edit(insert_str, parent_decl.end_byte());
-                        continue;
+        let mut callee_name = callee.to_source(code);
+        /* Whether the method is called on a list of instructions, e.g., `BB->getInstList().push_back(I)` */
+        let mut on_list = false;
+        match callee.kind() {
+            "field_expression" => {
+                callee_name = self.field(&callee, "field", code)?.to_source(code);
+                if let Some(accessor) = list_accessor(&callee, code) {
+                    let list_method = format!("{}().{}", accessor, callee_name);
+                    if list_method.get_fn_kind(&self.catalog).is_some() {
+                        callee_name = list_method;
+                        on_list = true;
                     }
+                }
+            }
+            /* Free helpers, e.g., `ReplaceInstWithInst(...)` or `llvm::ReplaceInstWithInst(...)` */
+            "identifier" | "qualified_identifier" => {
+                let helper = callee_name.strip_prefix("llvm::").unwrap_or(&callee_name);
+                if helper.to_string().get_fn_kind(&self.catalog) == Some(FnKind::Replace) {
+                    callee_name = helper.to_string();
+                } else if callee.kind() == "identifier" {
+                    return Ok(());
+                }
+            }
+            _ => return Ok(()),
+        };
+
+        let Some(kind) = callee_name.get_fn_kind(&self.catalog) else {
+            return Ok(());
+        };
+        if let Err(diag) = self.visit_matched_call(kind, call, callee, callee_name, on_list, code) {
+            self.skip_site(kind, &call, code, diag);
+        }
+        Ok(())
+    }
 
-                    if let Some(parent_assign) = get_parent_of_kind(&call, "assignment_expression")
-                    {
-                        let var_name = get_var_name_from_assign(&parent_assign);
+    /// Instrument a call of the catalog, or report it as skipped
+    fn visit_matched_call(
+        &mut self,
+        kind: FnKind,
+        call: Node,
+        callee: Node,
+        callee_name: String,
+        on_list: bool,
+        code: &str,
+    ) -> Result<(), Diagnostic> {
+        let arguments = self.field(&call, "arguments", code)?;
 
-                        let insert_str = format!("{{ ");
-                        self.add_insert_edit(insert_str, parent_assign.start_byte());
+        /* Other classes may have methods named alike, e.g., `BB->moveBefore(...)` */
+        if kind.is_method()
+            && callee.kind() == "field_expression"
+            && !on_list
+            && !self.accepts_receiver(kind, &call, &callee, code)?
+        {
+            return Ok(());
+        }
+
+        /* The hooks of these are put around the statement of the call,
+         * e.g., `{ I->moveBefore(P); hook::OnMove(...); }` */
+        if matches!(
+            kind,
+            FnKind::Move
+                | FnKind::Insert
+                | FnKind::Splice
+                | FnKind::UseReplace
+                | FnKind::Remove
+                | FnKind::DLPreserve
+                | FnKind::DLMerge
+                | FnKind::DLDrop
+        ) && call.parent().map(|p| p.kind()).unwrap_or_default() != "expression_statement"
+        {
+            self.report_skip(kind, &call, code, "not an expression statement");
+            return Ok(());
+        }
+        /* The hooks of these evaluate the object again, e.g., `I` of `hook::OnRemove(I, ...)`,
+         * but not the list of `BB->getInstList().push_back(I)` */
+        if matches!(
+            kind,
+            FnKind::Clone
+                | FnKind::Move
+                | FnKind::Remove
+                | FnKind::DLPreserve
+                | FnKind::DLMerge
+                | FnKind::DLDrop
+        ) || (kind == FnKind::Insert && !on_list)
+        {
+            let object = self.field(&callee, "argument", code)?;
+            if has_side_effects(&object, code) {
+                let reason = format!(
+                    "the object `{}` cannot be evaluated again",
+                    object.to_source(code)
+                );
+                self.report_skip(kind, &call, code, &reason);
+                return Ok(());
+            }
+        }
 
-                        let insert_str = format!(
-                            " {}; }}",
-                            hook::on_create(
-                                &var_name.to_source(code),
-                                parent_assign.row(),
-                                &var_name.to_source(code)
-                            )
+        match kind {
+            FnKind::Create => match destination(&call) {
+                Destination::Decl(parent_decl, declarator) => {
+                    let var_name = self.var_name_from_decl(&declarator, code)?;
+                    let hook_str = hook::on_create(&var_name, parent_decl.row(), &var_name);
+                    self.add_insert_edit(&call, format!(" {};", hook_str), parent_decl.end_byte());
+                    self.report_hook(FnKind::Create, &call, code, &[&var_name], &hook_str);
+                }
+                Destination::Assign(parent_assign) => {
+                    let var_name = self.var_name_from_assign(&parent_assign, code)?;
+
+                    let hook_str = hook::on_create(&var_name, parent_assign.row(), &var_name);
+                    self.add_wrap_edit(
+                        &call,
+                        "{ ".to_string(),
+                        statement_range(&parent_assign),
+                        format!(" {}; }}", hook_str),
+                    );
+                    self.report_hook(FnKind::Create, &call, code, &[&var_name], &hook_str);
+                }
+                Destination::Return(parent_return) => {
+                    let temp = self.fresh_name("V");
+                    let hook_str = hook::on_create(&temp, parent_return.row(), "");
+                    let content = hoist_from_return(
+                        &format!("auto *{}", temp),
+                        &call,
+                        &parent_return,
+                        &hook_str,
+                        &temp,
+                    );
+                    self.add_replace_edit(&call, content, parent_return.byte_range());
+                    self.report_hook(FnKind::Create, &call, code, &[], &hook_str);
+                }
+                /* Function calls like `BranchInst::Create(...);`, kept one expression
+                 * for the unbraced bodies, e.g., `if (C) BranchInst::Create(...);` */
+                dest @ (Destination::Discarded | Destination::Bound(_) | Destination::Operand) => {
+                    let var_name = self.bound_var_name(&dest, code)?;
+                    let (prefix, suffix) =
+                        hook::created(call.row(), var_name.as_deref().unwrap_or_default());
+                    self.add_wrap_edit(&call, prefix.clone(), call.byte_range(), suffix.clone());
+                    let hook_str = format!("{}{}{}", prefix, call.to_source(code), suffix);
+                    let vars: Vec<&str> = var_name.as_deref().into_iter().collect();
+                    self.report_hook(FnKind::Create, &call, code, &vars, &hook_str);
+                }
+            },
+            /* Value *V = Builder.CreateAdd(...); */
+            FnKind::BuilderCreate if callee.kind() == "field_expression" => {
+                let (builder, _) = self.called_object(&callee, code)?;
+                match destination(&call) {
+                    Destination::Decl(parent_decl, declarator) => {
+                        let var_name = self.var_name_from_decl(&declarator, code)?;
+                        let hook_str = hook::on_builder_create(
+                            &var_name,
+                            parent_decl.row(),
+                            &var_name,
+                            &builder,
+                        );
+                        self.add_insert_edit(
+                            &call,
+                            format!(" {};", hook_str),
+                            parent_decl.end_byte(),
+                        );
+                        self.report_hook(
+                            FnKind::BuilderCreate,
+                            &call,
+                            code,
+                            &[&var_name, &builder],
+                            &hook_str,
                         );
-                        self.add_insert_edit(insert_str, parent_assign.end_byte() + 1);
-                        continue;
                     }
-
-                    if let Some(parent_return) = get_parent_of_kind(&call, "return_statement") {
-                        let replace_str = format!(
-                            "{{ auto *V = {}; {}; return V; }}",
-                            call.to_source(code),
-                            hook::on_create("V", parent_return.row(), ""),
+                    Destination::Assign(parent_assign) => {
+                        let var_name = self.var_name_from_assign(&parent_assign, code)?;
+                        let hook_str = hook::on_builder_create(
+                            &var_name,
+                            parent_assign.row(),
+                            &var_name,
+                            &builder,
                         );
-                        self.add_replace_edit(
-                            replace_str,
-                            parent_return.start_byte(),
-                            parent_return.end_byte(),
+                        self.add_wrap_edit(
+                            &call,
+                            "{ ".to_string(),
+                            statement_range(&parent_assign),
+                            format!(" {}; }}", hook_str),
+                        );
+                        self.report_hook(
+                            FnKind::BuilderCreate,
+                            &call,
+                            code,
+                            &[&var_name, &builder],
+                            &hook_str,
                         );
-                        continue;
                     }
-
-                    // Function calls like BranchInst::Create(...)
-                    if let Some(parent) = call.parent() {
-                        if parent.kind() == "expression_statement" {
-                            let replace_str = format!(
-                                "Instruction *I = {}; {};",
-                                call.to_source(code),
-                                hook::on_create("I", call.row(), "I"),
-                            );
-                            self.add_replace_edit(replace_str, call.start_byte(), call.end_byte());
-                        }
+                    Destination::Return(parent_return) => {
+                        let temp = self.fresh_name("V");
+                        let hook_str =
+                            hook::on_builder_create(&temp, parent_return.row(), "", &builder);
+                        let content = hoist_from_return(
+                            &format!("auto *{}", temp),
+                            &call,
+                            &parent_return,
+                            &hook_str,
+                            &temp,
+                        );
+                        self.add_replace_edit(&call, content, parent_return.byte_range());
+                        self.report_hook(
+                            FnKind::BuilderCreate,
+                            &call,
+                            code,
+                            &[&builder],
+                            &hook_str,
+                        );
                     }
-                }
-                /* auto *NI = OI->clone(); */
-                Some(FnKind::Clone) => {
-                    let original_inst = callee.child_by_field_name("argument").unwrap();
-                    let addr_op = if callee.child(1).unwrap().to_source(code).as_str() == "->" {
-                        ""
-                    } else {
-                        "&"
-                    };
-                    if let Some(parent_decl) = get_parent_of_kind(&call, "declaration") {
-                        let var_name = get_var_name_from_decl(&parent_decl);
-
-                        let insert_str = format!(
-                            " {};",
-                            hook::on_clone(
-                                &var_name.to_source(code),
-                                &format!("{}{}", addr_op, original_inst.to_source(code)),
-                                parent_decl.row(),
-                                &var_name.to_source(code),
-                                &original_inst.to_source(code),
-                            )
+                    /* Most builder methods return a `Value *`, e.g., `Builder.CreateStore(...);` */
+                    dest @ (Destination::Discarded
+                    | Destination::Bound(_)
+                    | Destination::Operand) => {
+                        let var_name = self.bound_var_name(&dest, code)?;
+                        let (prefix, suffix) = hook::builder_created(
+                            call.row(),
+                            var_name.as_deref().unwrap_or_default(),
+                            &builder,
+                        );
+                        self.add_wrap_edit(
+                            &call,
+                            prefix.clone(),
+                            call.byte_range(),
+                            suffix.clone(),
                         );
-                        self.add_insert_edit(insert_str, parent_decl.end_byte());
-                        continue;
+                        let hook_str = format!("{}{}{}", prefix, call.to_source(code), suffix);
+                        let vars: Vec<&str> = var_name
+                            .iter()
+                            .chain([&builder])
+                            .map(String::as_str)
+                            .collect();
+                        self.report_hook(FnKind::BuilderCreate, &call, code, &vars, &hook_str);
                     }
+                }
+            }
+            /* auto *NI = OI->clone(); */
+            FnKind::Clone => {
+                let (original_name, original_ptr) = self.called_object(&callee, code)?;
+                match destination(&call) {
+                    Destination::Decl(parent_decl, declarator) => {
+                        let var_name = self.var_name_from_decl(&declarator, code)?;
 
-                    if let Some(parent_assign) = get_parent_of_kind(&call, "assignment_expression")
-                    {
-                        let var_name = get_var_name_from_assign(&parent_assign);
-
-                        let insert_str = format!("{{ ");
-                        self.add_insert_edit(insert_str, parent_assign.start_byte());
-
-                        let insert_str = format!(
-                            " {}; }}",
-                            hook::on_clone(
-                                &var_name.to_source(code),
-                                &format!("{}{}", addr_op, original_inst.to_source(code)),
-                                parent_assign.row(),
-                                &var_name.to_source(code),
-                                &original_inst.to_source(code),
-                            )
+                        let hook_str = hook::on_clone(
+                            &var_name,
+                            &original_ptr,
+                            parent_decl.row(),
+                            &var_name,
+                            &original_name,
+                        );
+                        self.add_insert_edit(
+                            &call,
+                            format!(" {};", hook_str),
+                            parent_decl.end_byte(),
+                        );
+                        self.report_hook(
+                            FnKind::Clone,
+                            &call,
+                            code,
+                            &[&var_name, &original_name],
+                            &hook_str,
                         );
-                        self.add_insert_edit(insert_str, parent_assign.end_byte() + 1);
-                        continue;
                     }
+                    Destination::Assign(parent_assign) => {
+                        let var_name = self.var_name_from_assign(&parent_assign, code)?;
 
-                    call.dump_source(code);
-                    // panic!("Failed to parse instruction clone!");
-                }
-                /* I->moveBefore(D, ..); */
-                Some(FnKind::Move) => {
-                    let move_target = callee.child_by_field_name("argument").unwrap();
-                    let field_op = callee.child(1).unwrap().to_source(code);
-                    let ref_op = if field_op.as_str() == "->" { "" } else { "&" };
-
-                    self.add_insert_edit(String::from("{ "), call.start_byte());
-
-                    let insert_str = format!(
-                        " {}; }}",
-                        hook::on_move(
-                            &format!("{}{}", ref_op, move_target.to_source(code)),
-                            call.row(),
-                            &move_target.to_source(code),
-                        )
-                    );
-                    self.add_insert_edit(insert_str, call.end_byte() + 1);
-                }
-                Some(FnKind::UseReplace) => {
-                    /* The S-expr of `DLS->replaceAllUsesWith(DLD)` is shown as following:
-                     *  (call_expression
-                     *       function: (field_expression
-                     *           argument: (identifier)
-                     *           field: (field_identifier)
-                     *       )
-                     *       arguments: (argument_list
-                     *           (identifier)
-                     *       )
-                     *  )
-                     */
-                    if callee_name.as_str() == "replaceAllUsesWith" {
-                        let debugloc_src = callee.child_by_field_name("argument").unwrap();
-                        let debugloc_dst = arguments.child(1).unwrap();
-
-                        let field_operator = callee.child(1).unwrap().to_source(code);
-                        let prepare_str = format!(
-                            "Value *DebugLocSrc = {}{}; Value *DebugLocDst = {};",
-                            if field_operator.as_str() == "." {
-                                "&"
-                            } else {
-                                ""
-                            },
-                            debugloc_src.to_source(code),
-                            debugloc_dst.to_source(code),
+                        let hook_str = hook::on_clone(
+                            &var_name,
+                            &original_ptr,
+                            parent_assign.row(),
+                            &var_name,
+                            &original_name,
                         );
-                        let replace_str = format!("DebugLocSrc->replaceAllUsesWith(DebugLocDst);");
-
-                        let hook_str = format!(
-                            "{};",
-                            hook::on_use_replace(
-                                "DebugLocSrc",
-                                "DebugLocDst",
-                                call.row(),
-                                &debugloc_dst.to_source(code),
-                                &debugloc_src.to_source(code),
-                            )
+                        self.add_wrap_edit(
+                            &call,
+                            "{ ".to_string(),
+                            statement_range(&parent_assign),
+                            format!(" {}; }}", hook_str),
+                        );
+                        self.report_hook(
+                            FnKind::Clone,
+                            &call,
+                            code,
+                            &[&var_name, &original_name],
+                            &hook_str,
                         );
-
-                        let replace_str =
-                            format!("{{ {} {} {} }}", prepare_str, replace_str, hook_str);
-                        self.add_replace_edit(replace_str, call.start_byte(), call.end_byte() + 1);
                     }
-                    if callee_name.as_str() == "replaceUsesOfWith" {
-                        if call.parent().unwrap().kind() != "expression_statement" {
-                            // panic!("{}\n  {} {}", "Non expression statement parent!".red().bold(), "-->".blue().bold(), call.parent().unwrap().to_source(code));
-                            continue;
-                        }
-
-                        let called_obj = callee.child_by_field_name("argument").unwrap();
-                        let old_inst = arguments.child(1).unwrap();
-                        let new_inst = arguments.child(3).unwrap();
-
-                        let field_operator = callee.child(1).unwrap().to_source(code);
-                        let prepare_str = format!(
-                            "Value *DebugLocSrc = {}; Value *DebugLocDst = {};",
-                            old_inst.to_source(code),
-                            new_inst.to_source(code)
+                    Destination::Return(parent_return) => {
+                        let temp = self.fresh_name("V");
+                        let hook_str = hook::on_clone(
+                            &temp,
+                            &original_ptr,
+                            parent_return.row(),
+                            "",
+                            &original_name,
                         );
-
-                        let inst_repl_str = format!(
-                            "{}{}replaceUsesOfWith(DebugLocSrc, DebugLocDst);",
-                            called_obj.to_source(code),
-                            field_operator
+                        let content = hoist_from_return(
+                            &format!("auto *{}", temp),
+                            &call,
+                            &parent_return,
+                            &hook_str,
+                            &temp,
                         );
-                        let hook_str = format!(
-                            "{};",
-                            hook::on_use_replace(
-                                "DebugLocSrc",
-                                "DebugLocDst",
-                                call.row(),
-                                &new_inst.to_source(code),
-                                &old_inst.to_source(code),
-                            )
+                        self.add_replace_edit(&call, content, parent_return.byte_range());
+                        self.report_hook(FnKind::Clone, &call, code, &[&original_name], &hook_str);
+                    }
+                    /* A discarded clone is leaked, but still tracked */
+                    dest @ (Destination::Discarded
+                    | Destination::Bound(_)
+                    | Destination::Operand) => {
+                        let var_name = self.bound_var_name(&dest, code)?;
+                        let (prefix, suffix) = hook::cloned(
+                            &original_ptr,
+                            call.row(),
+                            var_name.as_deref().unwrap_or_default(),
+                            &original_name,
                         );
-
-                        let replace_str =
-                            format!("{{ {} {} {} }}", prepare_str, inst_repl_str, hook_str);
-                        self.add_replace_edit(replace_str, call.start_byte(), call.end_byte() + 1);
+                        self.add_wrap_edit(
+                            &call,
+                            prefix.clone(),
+                            call.byte_range(),
+                            suffix.clone(),
+                        );
+                        let hook_str = format!("{}{}{}", prefix, call.to_source(code), suffix);
+                        let vars: Vec<&str> = var_name
+                            .iter()
+                            .chain([&original_name])
+                            .map(String::as_str)
+                            .collect();
+                        self.report_hook(FnKind::Clone, &call, code, &vars, &hook_str);
                     }
                 }
-                Some(FnKind::Remove) => {
-                    let called_obj = callee.child_by_field_name("argument").unwrap();
-                    let insert_str = format!(
-                        "{{ {}; ",
-                        hook::on_remove(
-                            &called_obj.to_source(code),
-                            call.start_position().row,
-                            &called_obj.to_source(code)
-                        )
+            }
+            /* I->moveBefore(D, ..); */
+            FnKind::Move => {
+                let (target_name, target_ptr) = self.called_object(&callee, code)?;
+                let hook_str = hook::on_move(&target_ptr, call.row(), &target_name);
+                self.add_wrap_edit(
+                    &call,
+                    "{ ".to_string(),
+                    statement_range(&call),
+                    format!(" {}; }}", hook_str),
+                );
+                self.report_hook(FnKind::Move, &call, code, &[&target_name], &hook_str);
+            }
+            /* I->insertBefore(Pos); or BB->getInstList().push_back(I); */
+            FnKind::Insert => {
+                let (inst_name, inst_ptr) = if on_list {
+                    /* The instruction inserted is the last argument, evaluated once */
+                    let inst = arguments
+                        .named_child(arguments.named_child_count().saturating_sub(1))
+                        .ok_or_else(|| {
+                            self.error_at(&arguments, code, "no instruction inserted")
+                        })?;
+                    if inst.kind() != "identifier" {
+                        self.report_skip(
+                            FnKind::Insert,
+                            &call,
+                            code,
+                            "the instruction inserted is not a variable",
+                        );
+                        return Ok(());
+                    }
+                    (inst.to_source(code), inst.to_source(code))
+                } else {
+                    self.called_object(&callee, code)?
+                };
+                let hook_str = hook::on_insert(&inst_ptr, call.row(), &inst_name);
+                self.add_wrap_edit(
+                    &call,
+                    "{ ".to_string(),
+                    statement_range(&call),
+                    format!(" {}; }}", hook_str),
+                );
+                self.report_hook(FnKind::Insert, &call, code, &[&inst_name], &hook_str);
+            }
+            /* ReplaceInstWithInst(From, To); or Changed |= replaceDominatedUsesWith(...); */
+            FnKind::Replace => {
+                let args = call_arguments(&arguments);
+                let Some(model) = replace_model(&callee_name, args.len()) else {
+                    let reason = format!(
+                        "no model of `{}` with {} arguments",
+                        callee_name,
+                        args.len()
                     );
-                    self.add_insert_edit(insert_str, call.start_byte());
-                    let insert_str = format!("}}");
-                    self.add_insert_edit(insert_str, call.end_byte() + 1);
-                }
-                Some(FnKind::DLPreserve) => {
-                    let dst_inst = callee.child_by_field_name("argument").unwrap();
-                    let insert_str = format!("{{ ");
-                    self.add_insert_edit(insert_str, call.start_byte());
-
-                    let insert_str = format!(
-                        " {}; }}",
-                        hook::on_preserve(&dst_inst.to_source(code), call.row())
+                    self.report_skip(FnKind::Replace, &call, code, &reason);
+                    return Ok(());
+                };
+                let Some(stmt) = enclosing_statement(&call) else {
+                    self.report_skip(
+                        FnKind::Replace,
+                        &call,
+                        code,
+                        "not in an expression statement",
                     );
-                    self.add_insert_edit(insert_str, call.end_byte() + 1);
-                }
-                Some(FnKind::DLMerge) => {
-                    let dst_inst = callee.child_by_field_name("argument").unwrap();
-                    let insert_str = format!("{{ ");
-                    self.add_insert_edit(insert_str, call.start_byte());
-
-                    let insert_str = format!(
-                        " {}; }}",
-                        hook::on_merge(&dst_inst.to_source(code), call.row())
+                    return Ok(());
+                };
+                /* The hooks evaluate the operands again, before and after the call */
+                let src = args[model.src.index()];
+                if has_side_effects(&src, code) {
+                    let reason = format!(
+                        "the source `{}` cannot be evaluated again",
+                        src.to_source(code)
                     );
-                    self.add_insert_edit(insert_str, call.end_byte() + 1);
+                    self.report_skip(FnKind::Replace, &call, code, &reason);
+                    return Ok(());
                 }
-                Some(FnKind::DLDrop) => {
-                    let dst_inst = callee.child_by_field_name("argument").unwrap();
-                    let insert_str = format!("{{ ");
-                    self.add_insert_edit(insert_str, call.start_byte());
-
-                    let insert_str = format!(
-                        " {}; }}",
-                        hook::on_drop(&dst_inst.to_source(code), call.row())
-                    );
-                    self.add_insert_edit(insert_str, call.end_byte() + 1);
+                /* The helpers move the iterator given, e.g., `BI` of `ReplaceInstWithValue(BI, V)` */
+                let reads_iterator = |dst: &Node| {
+                    matches!(model.src, Operand::Pointee(_))
+                        && get_identifiers(dst, code).contains(&src.to_source(code))
+                };
+                let dst = match model.dst.map(|dst| args[dst.index()]) {
+                    Some(dst) if !has_side_effects(&dst, code) && !reads_iterator(&dst) => {
+                        Some(dst)
+                    }
+                    /* e.g., `ReplaceInstWithInst(I, BinaryOperator::Create(...))`, found in place of `I` */
+                    _ if model.in_place => None,
+                    dst => {
+                        let reason = format!(
+                            "the destination `{}` cannot be evaluated again",
+                            dst.map(|dst| dst.to_source(code)).unwrap_or_default()
+                        );
+                        self.report_skip(FnKind::Replace, &call, code, &reason);
+                        return Ok(());
+                    }
+                };
+
+                let src_name = src.to_source(code);
+                let dst_name = dst.map(|dst| dst.to_source(code)).unwrap_or_default();
+                let dst_ptr = dst.map_or_else(|| "nullptr".to_string(), |_| dst_name.clone());
+                let temp = self.fresh_name("Replacement");
+                let start_str = hook::on_replace_start(&model.src.pointer(&src_name), &src_name);
+                let hook_str = hook::on_replace(&temp, &dst_ptr, call.row(), &dst_name);
+                self.add_wrap_edit(
+                    &call,
+                    format!("{{ auto {} = {}; ", temp, start_str),
+                    stmt.byte_range(),
+                    format!(" {}; }}", hook_str),
+                );
+                let vars: Vec<&str> = [src_name.as_str(), dst_name.as_str()]
+                    .into_iter()
+                    .filter(|var| !var.is_empty())
+                    .collect();
+                let hooks = format!("{}; {}", start_str, hook_str);
+                self.report_hook(FnKind::Replace, &call, code, &vars, &hooks);
+            }
+            /* BB->splice(BB->end(), FromBB); */
+            FnKind::Splice if callee.kind() == "field_expression" => {
+                let Some(from) = call_arguments(&arguments).get(1).copied() else {
+                    self.report_skip(FnKind::Splice, &call, code, "no block spliced from");
+                    return Ok(());
+                };
+                let from_name = from.to_source(code);
+                if has_side_effects(&from, code) {
+                    let reason = format!("the block `{}` cannot be evaluated again", from_name);
+                    self.report_skip(FnKind::Splice, &call, code, &reason);
+                    return Ok(());
                 }
-                _ => {}
-            };
-        }
-    }
 
-    fn visit_new_exprs(&mut self, nodes: Vec<Node>, code: &str) {
-        for new in nodes {
-            let new_type = new.child_by_field_name("type").unwrap();
-            let new_type_str = new_type.to_source(code);
-            if let Some(FnKind::Create) = new_type_str.get_fn_kind() {
-                if let Some(parent_decl) = get_parent_of_kind(&new, "declaration") {
-                    let var_name = get_var_name_from_decl(&parent_decl);
-                    let insert_str = format!(
-                        " {};",
-                        hook::on_create(
-                            &var_name.to_source(code),
-                            new.row(),
-                            &var_name.to_source(code),
-                        )
+                let temp = self.fresh_name("Spliced");
+                let start_str = hook::on_splice_start(&from_name);
+                let hook_str = hook::on_splice(&temp, call.row(), &from_name);
+                self.add_wrap_edit(
+                    &call,
+                    format!("{{ auto {} = {}; ", temp, start_str),
+                    statement_range(&call),
+                    format!(" {}; }}", hook_str),
+                );
+                let hooks = format!("{}; {}", start_str, hook_str);
+                self.report_hook(FnKind::Splice, &call, code, &[&from_name], &hooks);
+            }
+            FnKind::UseReplace => {
+                /* The S-expr of `DLS->replaceAllUsesWith(DLD)` is shown as following:
+                 *  (call_expression
+                 *       function: (field_expression
+                 *           argument: (identifier)
+                 *           field: (field_identifier)
+                 *       )
+                 *       arguments: (argument_list
+                 *           (identifier)
+                 *       )
+                 *  )
+                 */
+                if callee_name.as_str() == "replaceAllUsesWith" {
+                    let debugloc_src = self.field(&callee, "argument", code)?;
+                    let debugloc_dst = self.child(&arguments, 1, code)?;
+
+                    let field_operator = self.child(&callee, 1, code)?.to_source(code);
+                    let (src_temp, dst_temp) = (
+                        self.fresh_name("DebugLocSrc"),
+                        self.fresh_name("DebugLocDst"),
                     );
+                    let addr_op = if field_operator.as_str() == "." {
+                        "&"
+                    } else {
+                        ""
+                    };
 
-                    self.add_insert_edit(insert_str, parent_decl.end_byte());
-                    continue;
-                }
+                    let (dst_name, src_name) =
+                        (debugloc_dst.to_source(code), debugloc_src.to_source(code));
+                    let hook_str = hook::on_use_replace(
+                        &src_temp,
+                        &dst_temp,
+                        call.row(),
+                        &dst_name,
+                        &src_name,
+                    );
 
-                if let Some(parent_assign) = get_parent_of_kind(&new, "assignment_expression") {
-                    let var_name = get_var_name_from_assign(&parent_assign);
+                    let content = vec![
+                        Segment::Text(format!("{{ Value *{} = {}", src_temp, addr_op)),
+                        Segment::Source(debugloc_src.byte_range()),
+                        Segment::Text(format!("; Value *{} = ", dst_temp)),
+                        Segment::Source(debugloc_dst.byte_range()),
+                        Segment::Text(format!(
+                            "; {}->replaceAllUsesWith({}); {}; }}",
+                            src_temp, dst_temp, hook_str
+                        )),
+                    ];
+                    self.add_replace_edit(&call, content, statement_range(&call));
+                    self.report_hook(
+                        FnKind::UseReplace,
+                        &call,
+                        code,
+                        &[&src_name, &dst_name],
+                        &hook_str,
+                    );
+                }
+                if callee_name.as_str() == "replaceUsesOfWith" {
+                    let called_obj = self.field(&callee, "argument", code)?;
+                    let old_inst = self.child(&arguments, 1, code)?;
+                    let new_inst = self.child(&arguments, 3, code)?;
 
-                    let insert_str = format!("{{ ");
-                    self.add_insert_edit(insert_str, parent_assign.start_byte());
+                    let field_operator = self.child(&callee, 1, code)?.to_source(code);
+                    let (src_temp, dst_temp) = (
+                        self.fresh_name("DebugLocSrc"),
+                        self.fresh_name("DebugLocDst"),
+                    );
+                    let (new_name, old_name) = (new_inst.to_source(code), old_inst.to_source(code));
+                    let hook_str = hook::on_use_replace(
+                        &src_temp,
+                        &dst_temp,
+                        call.row(),
+                        &new_name,
+                        &old_name,
+                    );
 
-                    let insert_str = format!(
-                        " {}; }}",
-                        hook::on_create(
-                            &var_name.to_source(code),
-                            new.row(),
-                            &var_name.to_source(code),
-                        )
+                    let content = vec![
+                        Segment::Text(format!("{{ Value *{} = ", src_temp)),
+                        Segment::Source(old_inst.byte_range()),
+                        Segment::Text(format!("; Value *{} = ", dst_temp)),
+                        Segment::Source(new_inst.byte_range()),
+                        Segment::Text("; ".to_string()),
+                        Segment::Source(called_obj.byte_range()),
+                        Segment::Text(format!(
+                            "{}replaceUsesOfWith({}, {}); {}; }}",
+                            field_operator, src_temp, dst_temp, hook_str
+                        )),
+                    ];
+                    self.add_replace_edit(&call, content, statement_range(&call));
+                    self.report_hook(
+                        FnKind::UseReplace,
+                        &call,
+                        code,
+                        &[&old_name, &new_name],
+                        &hook_str,
                     );
+                }
+            }
+            FnKind::Remove => {
+                let (obj_name, obj_ptr) = self.called_object(&callee, code)?;
+                let hook_str = hook::on_remove(&obj_ptr, call.row(), &obj_name);
+                self.add_wrap_edit(
+                    &call,
+                    format!("{{ {}; ", hook_str),
+                    statement_range(&call),
+                    "}".to_string(),
+                );
+                self.report_hook(FnKind::Remove, &call, code, &[&obj_name], &hook_str);
+            }
+            /* I->setDebugLoc(Loc); which updates as `Loc` tells, e.g., `DebugLoc()` drops */
+            FnKind::DLPreserve => {
+                let (dst_name, dst_ptr) = self.called_object(&callee, code)?;
+                let hook_str = match call_arguments(&arguments).first() {
+                    Some(loc) => hook::on_set_debug_loc(
+                        &dst_ptr,
+                        location_update(loc, code),
+                        call.row(),
+                        &loc.to_source(code),
+                    ),
+                    None => hook::on_preserve(&dst_ptr, call.row()),
+                };
+                self.add_wrap_edit(
+                    &call,
+                    "{ ".to_string(),
+                    statement_range(&call),
+                    format!(" {}; }}", hook_str),
+                );
+                self.report_hook(FnKind::DLPreserve, &call, code, &[&dst_name], &hook_str);
+            }
+            FnKind::DLMerge => {
+                let (dst_name, dst_ptr) = self.called_object(&callee, code)?;
+                let hook_str = hook::on_merge(&dst_ptr, call.row());
+                self.add_wrap_edit(
+                    &call,
+                    "{ ".to_string(),
+                    statement_range(&call),
+                    format!(" {}; }}", hook_str),
+                );
+                self.report_hook(FnKind::DLMerge, &call, code, &[&dst_name], &hook_str);
+            }
+            FnKind::DLDrop => {
+                let (dst_name, dst_ptr) = self.called_object(&callee, code)?;
+                let hook_str = hook::on_drop(&dst_ptr, call.row());
+                self.add_wrap_edit(
+                    &call,
+                    "{ ".to_string(),
+                    statement_range(&call),
+                    format!(" {}; }}", hook_str),
+                );
+                self.report_hook(FnKind::DLDrop, &call, code, &[&dst_name], &hook_str);
+            }
+            _ => {}
+        };
+        Ok(())
+    }
 
-                    self.add_insert_edit(insert_str, parent_assign.end_byte() + 1);
-                    continue;
+    fn visit_new_exprs(&mut self, nodes: Vec<Node>, code: &str) {
+        for new in nodes {
+            if let Err(diag) = self.visit_new_expr(new, code) {
+                self.skip_site(FnKind::Create, &new, code, diag);
+            }
+        }
+    }
+
+    fn visit_new_expr(&mut self, new: Node, code: &str) -> Result<(), Diagnostic> {
+        let Some(new_type) = new.child_by_field_name("type") else {
+            return Ok(());
+        };
+        let new_type_str = new_type.to_source(code);
+        if let Some(FnKind::Create) = new_type_str.get_fn_kind(&self.catalog) {
+            match destination(&new) {
+                Destination::Decl(parent_decl, declarator) => {
+                    let var_name = self.var_name_from_decl(&declarator, code)?;
+                    let hook_str = hook::on_create(&var_name, new.row(), &var_name);
+
+                    self.add_insert_edit(&new, format!(" {};", hook_str), parent_decl.end_byte());
+                    self.report_hook(FnKind::Create, &new, code, &[&var_name], &hook_str);
                 }
+                Destination::Assign(parent_assign) => {
+                    let var_name = self.var_name_from_assign(&parent_assign, code)?;
 
-                if let Some(parent_return) = get_parent_of_kind(&new, "return_statement") {
-                    let insert_str = format!(
-                        "{{ Value *V = {}; {}; return V; }}",
-                        new.to_source(code),
-                        hook::on_create("V", new.row(), "")
+                    let hook_str = hook::on_create(&var_name, new.row(), &var_name);
+                    self.add_wrap_edit(
+                        &new,
+                        "{ ".to_string(),
+                        statement_range(&parent_assign),
+                        format!(" {}; }}", hook_str),
                     );
-                    self.add_replace_edit(
-                        insert_str,
-                        parent_return.start_byte(),
-                        parent_return.end_byte(),
+                    self.report_hook(FnKind::Create, &new, code, &[&var_name], &hook_str);
+                }
+                Destination::Return(parent_return) => {
+                    let temp = self.fresh_name("V");
+                    let hook_str = hook::on_create(&temp, new.row(), "");
+                    let content = hoist_from_return(
+                        &format!("Value *{}", temp),
+                        &new,
+                        &parent_return,
+                        &hook_str,
+                        &temp,
                     );
-                    continue;
+                    self.add_replace_edit(&new, content, parent_return.byte_range());
+                    self.report_hook(FnKind::Create, &new, code, &[], &hook_str);
+                }
+                /* e.g., `new StoreInst(...);` inserting itself */
+                dest @ (Destination::Discarded | Destination::Bound(_) | Destination::Operand) => {
+                    let var_name = self.bound_var_name(&dest, code)?;
+                    let (prefix, suffix) =
+                        hook::created(new.row(), var_name.as_deref().unwrap_or_default());
+                    self.add_wrap_edit(&new, prefix.clone(), new.byte_range(), suffix.clone());
+                    let hook_str = format!("{}{}{}", prefix, new.to_source(code), suffix);
+                    let vars: Vec<&str> = var_name.as_deref().into_iter().collect();
+                    self.report_hook(FnKind::Create, &new, code, &vars, &hook_str);
                 }
+            }
+        }
+        Ok(())
+    }
+}
 
-                println!(
-                    "{} {} {}:\n\t{} {}",
-                    "Warning".yellow().bold(),
-                    "Encounter an unsupported new expression at line".bold(),
-                    new.row(),
-                    "->".blue().bold(),
-                    new.to_source(code),
-                );
+/// Where the instruction created by an expression goes, which decides how
+/// the hook is placed around it
+enum Destination<'tree> {
+    /// `Instruction *I = Create(...);`, the declaration and the declarator of
+    /// the variable, one of several in `Instruction *A = ..., *B = ...;`
+    Decl(Node<'tree>, Node<'tree>),
+    /// `I = Create(...);`
+    Assign(Node<'tree>),
+    /// `return Create(...);`
+    Return(Node<'tree>),
+    /// `Create(...);`
+    Discarded,
+    /// A variable declared where no statement can follow, e.g., in
+    /// `if (auto *NewI = I->clone())` or `for (auto *I = Create(...); ...)`,
+    /// tracked in place by the declarator
+    Bound(Node<'tree>),
+    /// Any other use, e.g., `PN->addIncoming(Create(...), BB)`, tracked in place
+    Operand,
+}
+
+fn destination<'tree>(expr: &Node<'tree>) -> Destination<'tree> {
+    let Some(mut parent) = expr.parent() else {
+        return Destination::Operand;
+    };
+    let mut value = *expr;
+    /* Instruction *I{Create(...)}; */
+    if parent.kind() == "initializer_list" && parent.named_child_count() == 1 {
+        match parent.parent() {
+            Some(init) if init.kind() == "init_declarator" => {
+                value = parent;
+                parent = init;
             }
+            _ => return Destination::Operand,
+        }
+    }
+    let grandparent_kind = parent.parent().map(|p| p.kind()).unwrap_or_default();
+    match parent.kind() {
+        "init_declarator" if parent.child_by_field_name("value") == Some(value) => {
+            /* A structured binding has no variable to track */
+            if get_var_name_from_declarator(&parent).is_none() {
+                return Destination::Operand;
+            }
+            let decl = parent.parent().unwrap();
+            let in_block = decl
+                .parent()
+                .is_some_and(|p| matches!(p.kind(), "compound_statement" | "case_statement"));
+            if grandparent_kind == "declaration" && in_block {
+                Destination::Decl(decl, parent)
+            } else {
+                Destination::Bound(parent)
+            }
+        }
+        /* The declaration in a condition, which has no init declarator */
+        "declaration"
+            if parent.child_by_field_name("value") == Some(value)
+                && get_var_name_from_declarator(&parent).is_some() =>
+        {
+            Destination::Bound(parent)
+        }
+        "assignment_expression"
+            if parent.child_by_field_name("right") == Some(*expr)
+                && grandparent_kind == "expression_statement" =>
+        {
+            Destination::Assign(parent)
+        }
+        "return_statement" => Destination::Return(parent),
+        "expression_statement" => Destination::Discarded,
+        _ => Destination::Operand,
+    }
+}
+
+/// The statement a call or an assignment makes, with its semicolon, which may be
+/// apart from it, e.g., `I = Create(...) ;`
+fn statement_range(call: &Node) -> Range<usize> {
+    call.parent()
+        .map_or(call.byte_range(), |stmt| stmt.byte_range())
+}
+
+/// The arguments of a call, without the comments between them
+fn call_arguments<'tree>(arguments: &Node<'tree>) -> Vec<Node<'tree>> {
+    arguments
+        .named_children(&mut arguments.walk())
+        .filter(|arg| arg.kind() != "comment")
+        .collect()
+}
+
+/// The expression statement a call is evaluated in, e.g.,
+/// `Changed |= replaceDominatedUsesWith(...);`
+fn enclosing_statement<'tree>(call: &Node<'tree>) -> Option<Node<'tree>> {
+    let mut node = call.parent()?;
+    while matches!(
+        node.kind(),
+        "assignment_expression" | "parenthesized_expression" | "cast_expression"
+    ) {
+        node = node.parent()?;
+    }
+    (node.kind() == "expression_statement").then_some(node)
+}
+
+/// Whether evaluating an expression again may change something, taking the calls
+/// of accessors like `BB->getTerminator()` or `UndefValue::get(Ty)`, and casts,
+/// for free of side effects, but not the ones like `getOrInsertFunction`
+fn has_side_effects(expr: &Node, code: &str) -> bool {
+    let is_accessor = |call: &Node| {
+        let Some(function) = call.child_by_field_name("function") else {
+            return false;
+        };
+        let mut name = function;
+        while let Some(inner) = match name.kind() {
+            "field_expression" => name.child_by_field_name("field"),
+            "qualified_identifier" | "template_function" => name.child_by_field_name("name"),
+            _ => None,
+        } {
+            name = inner;
+        }
+        let name = name.to_source(code);
+        (name.starts_with("get") && !name.starts_with("getOr"))
+            || ACCESSOR_FN.contains(&name.as_str())
+            || CAST_FN.contains(&name.as_str())
+    };
+    let of_kind = |kind| {
+        let mut nodes = get_children_of_kind(expr, kind);
+        if expr.kind() == kind {
+            nodes.push(*expr);
+        }
+        nodes
+    };
+    [
+        "assignment_expression",
+        "update_expression",
+        "new_expression",
+    ]
+    .into_iter()
+    .any(|kind| !of_kind(kind).is_empty())
+        || of_kind("call_expression")
+            .iter()
+            .any(|call| !is_accessor(call))
+}
+
+/// The accessor returning the list a method is called on, e.g., `getInstList`
+/// for `BB->getInstList().push_back(I)`
+fn list_accessor(callee: &Node, code: &str) -> Option<String> {
+    let list = callee.child_by_field_name("argument")?;
+    let accessor = list.child_by_field_name("function")?;
+    if list.kind() != "call_expression"
+        || accessor.kind() != "field_expression"
+        || list.child_by_field_name("arguments")?.named_child_count() != 0
+    {
+        return None;
+    }
+    Some(accessor.child_by_field_name("field")?.to_source(code))
+}
+
+/// The names called in the pass entries other than the helpers, e.g., `runImpl`
+/// for `FooPass::run` calling `runImpl(F, DT)` or `Impl.runImpl(F)`
+fn called_by_entries(fn_defs: &[Node], code: &str) -> HashSet<String> {
+    fn_defs
+        .iter()
+        .filter(|fn_def| {
+            get_fn_identifier(fn_def).is_some_and(|ident| {
+                let fn_name = ident.to_source(code);
+                fn_name.is_pass_entry() && !fn_name.is_entry_helper()
+            })
+        })
+        .flat_map(|fn_def| get_children_of_kind(fn_def, "call_expression"))
+        .filter_map(|call| {
+            let function = call.child_by_field_name("function")?;
+            let name = match function.kind() {
+                "field_expression" => function.child_by_field_name("field")?,
+                "qualified_identifier" => function.child_by_field_name("name")?,
+                _ => function,
+            };
+            Some(name.to_source(code))
+        })
+        .collect()
+}
+
+/// The IR unit a parameter of the pass entry refers to, e.g., `F` for `Function &F`
+/// and `*L` for `Loop *L`
+fn pass_target(param: &Node, code: &str) -> Option<String> {
+    let ty = param.child_by_field_name("type")?.to_source(code);
+    if !PASS_TARGET_TYPES.contains(&ty.as_str()) {
+        return None;
+    }
+    let declarator = param.child_by_field_name("declarator")?;
+    match declarator.kind() {
+        "reference_declarator" => Some(declarator.named_child(0)?.to_source(code)),
+        "pointer_declarator" => Some(format!(
+            "*{}",
+            declarator
+                .child_by_field_name("declarator")?
+                .to_source(code)
+        )),
+        _ => None,
+    }
+}
+
+/// Hoist the expression returned, `expr`, into a temporary, so that the hook runs
+/// before returning, e.g., `return Create(...);` becomes
+/// `{ auto *V = Create(...); hook::OnCreate(V, ...); return V; }`.
+///
+/// An expression nested in the returned one, e.g., `return cast<X>(Create(...));`,
+/// is an operand tracked in place instead.
+fn hoist_from_return(
+    temp_decl: &str,
+    expr: &Node,
+    return_stmt: &Node,
+    hook_str: &str,
+    temp: &str,
+) -> Vec<Segment> {
+    vec![
+        Segment::Text(format!("{{ {} = ", temp_decl)),
+        Segment::Source(expr.byte_range()),
+        Segment::Text(format!("; {}; ", hook_str)),
+        Segment::Source(return_stmt.start_byte()..expr.start_byte()),
+        Segment::Text(temp.to_string()),
+        Segment::Source(expr.end_byte()..return_stmt.end_byte()),
+        Segment::Text(" }".to_string()),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn instrument(original: &str) -> String {
+        instrument_with_report(original).0
+    }
+
+    fn instrument_with_report(original: &str) -> (String, Report) {
+        let catalog = Catalog::load(None, None).unwrap();
+        let mut code = original.to_string();
+        let report = Instrumenter::new(&catalog)
+            .instrument("Pass.cpp", &mut code)
+            .unwrap_or_else(|diag| panic!("{}", diag.message));
+        (code, report)
+    }
+
+    /// A pass whose entry runs the body, from line 4 on
+    fn pass(body: &str) -> String {
+        format!(
+            "#include \"llvm/IR/Instructions.h\"\n\n\
+             PreservedAnalyses FooPass::run(Function &F, FunctionAnalysisManager &AM) {{\n\
+             {}\n  return PreservedAnalyses::none();\n}}\n",
+            body
+        )
+    }
+
+    #[test]
+    fn instrumented_files_keep_the_line_count() {
+        let body = "PreservedAnalyses FooPass::run(Function &F, FunctionAnalysisManager &AM) {
+  for (Instruction &I : instructions(F)) {
+    Instruction *NewI = I.clone();
+    NewI->insertBefore(&I);
+    I.replaceAllUsesWith(NewI);
+    I.eraseFromParent();
+  }
+  return PreservedAnalyses::none();
+}
+";
+        for preamble in [
+            /* A blank line before the includes */
+            "//===-- Pass.cpp --===//\n\n#include \"llvm/IR/Instructions.h\"\n",
+            /* A blank line after them */
+            "//===-- Pass.cpp --===//\n#include \"llvm/IR/Instructions.h\"\nusing namespace llvm;\n\n",
+            /* No blank line but the comment */
+            "//===-- Pass.cpp --===//\n#include \"llvm/IR/Instructions.h\"\n",
+        ] {
+            let original = format!("{}{}", preamble, body);
+            let code = instrument(&original);
+            assert!(code.contains(hook::HEADER_INCLUDE.trim_end()));
+            assert_eq!(code.lines().count(), original.lines().count(), "{}", code);
+        }
+    }
+
+    #[test]
+    fn instrumented_entries_are_found() {
+        let helper = instrument(
+            "#include \"llvm/IR/Instructions.h\"
+
+static void erase(Instruction *I) {
+  I->eraseFromParent();
+}
+",
+        );
+        let entry = instrument(&pass("  BranchInst::Create(&F.front());"));
+        let mut instrumenter = Instrumenter::new(&Catalog::load(None, None).unwrap());
+        assert!(instrumenter.is_instrumented(&helper) && !instrumenter.has_entry(&helper));
+        assert!(instrumenter.is_instrumented(&entry) && instrumenter.has_entry(&entry));
+    }
+
+    #[test]
+    fn entries_start_the_analysis_once() {
+        let code = instrument(
+            "#include \"llvm/IR/Instructions.h\"
+
+static bool runImpl(Function &F, DominatorTree &DT) {
+  BranchInst::Create(&F.front());
+  return true;
+}
+
+PreservedAnalyses FooPass::run(Function &F, FunctionAnalysisManager &AM) {
+  if (!runImpl(F, AM.getResult<DominatorTreeAnalysis>(F)))
+    return PreservedAnalyses::all();
+  return PreservedAnalyses::none();
+}
+",
+        );
+        /* Under the guard of `run` */
+        assert_eq!(code.matches("hook::OnStart(").count(), 1);
+        assert!(
+            code.contains("FunctionAnalysisManager &AM) { auto FinishGuard = hook::OnStart(F, ")
+        );
+        assert!(code.contains("hook::Created(BranchInst::Create(&F.front()), 4, \"\");"));
+
+        let code = instrument(
+            "#include \"llvm/IR/Instructions.h\"
+
+PreservedAnalyses BarPass::run(Module &M, ModuleAnalysisManager &AM) {
+  return PreservedAnalyses::none();
+}
+
+bool LegacyPass::runOnFunction(Function &F) {
+  return runImpl(F);
+}
+
+bool runImpl(Loop *L) {
+  return false;
+}
+",
+        );
+        assert!(code.contains("ModuleAnalysisManager &AM) { auto FinishGuard = hook::OnStart(M, "));
+        assert!(code.contains("runOnFunction(Function &F) { auto FinishGuard = hook::OnStart(F, "));
+        /* Under the guard of the legacy entry */
+        assert!(code.contains("runImpl(Loop *L) {\n"));
+    }
+
+    #[test]
+    fn finish_guards_keep_directives_on_their_lines() {
+        let original = "#include \"llvm/IR/Instructions.h\"
+
+PreservedAnalyses FooPass::run(Function &F, FunctionAnalysisManager &AM) {
+#ifndef NDEBUG
+  verifyFunction(F);
+#endif
+  BranchInst::Create(&F.front());
+  return PreservedAnalyses::none();
+}
+";
+        let code = instrument(original);
+        assert!(code.contains(
+            "FunctionAnalysisManager &AM) { auto FinishGuard = hook::OnStart(F, \"Pass.cpp\");\n#ifndef NDEBUG\n"
+        ));
+        assert_eq!(code.lines().count(), original.lines().count());
+    }
+
+    #[test]
+    fn objects_with_side_effects_are_evaluated_once() {
+        let code = instrument(
+            "#include \"llvm/IR/Instructions.h\"
+
+PreservedAnalyses FooPass::run(Function &F, FunctionAnalysisManager &AM) {
+  WL.pop_back_val()->eraseFromParent();
+  cast<Instruction>(I->clone())->setDebugLoc(DL);
+  cast<Instruction>(V)->eraseFromParent();
+  return PreservedAnalyses::none();
+}
+",
+        );
+        assert_eq!(code.matches("pop_back_val()").count(), 1);
+        assert!(!code.contains("hook::OnSetDebugLoc("));
+        assert!(code.contains("{ hook::OnRemove(cast<Instruction>(V), 6, "));
+    }
+
+    #[test]
+    fn methods_on_builder_results_are_instrumented() {
+        let code = instrument(
+            "#include \"llvm/IR/Instructions.h\"
+
+PreservedAnalyses FooPass::run(Function &F, FunctionAnalysisManager &AM) {
+  auto *CI = Builder.CreateCall(Fn, Args);
+  CI->setDebugLoc(DL);
+  auto *LI = Builder.CreateLoad(Ty, Ptr);
+  LI->moveBefore(Pos);
+  auto *Sum = Builder.CreateAdd(A, B);
+  Sum->moveBefore(Pos);
+  return PreservedAnalyses::none();
+}
+",
+        );
+        assert!(code.contains("hook::OnSetDebugLoc(CI, "));
+        assert!(code.contains("hook::OnMove(LI, "));
+        /* May be folded into a constant */
+        assert!(!code.contains("hook::OnMove(Sum, "));
+    }
+
+    #[test]
+    fn inserted_clones_are_tracked_once() {
+        let code = instrument(
+            "#include \"llvm/IR/Instructions.h\"
+
+static void copy(Instruction *I, Instruction *NewI, Instruction *J) {
+  I->clone()->insertBefore(J);
+  NewI->insertBefore(J);
+}
+",
+        );
+        assert_eq!(code.matches("clone()").count(), 1);
+        assert!(code.contains("{ NewI->insertBefore(J); hook::OnInsert(NewI, 5, \"NewI\"); }"));
+    }
+
+    #[test]
+    fn skipped_sites_are_reported_with_their_reasons() {
+        let (_, report) = instrument_with_report(&pass(
+            "  Member->eraseFromParent();
+  cast<Instruction>(WL.pop_back_val())->eraseFromParent();
+  BranchInst::Create(&F.front());",
+        ));
+        assert_eq!((report.instrumented, report.skipped), (1, 2));
+
+        let json: serde_json::Value = serde_json::from_str(&report.to_json()).unwrap();
+        let skipped: Vec<(u64, &str)> = json["sites"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter(|site| site.get("hook").is_none())
+            .map(|site| {
+                let reason = site["skipped"].as_str().unwrap();
+                (site["line"].as_u64().unwrap(), reason)
+            })
+            .collect();
+        assert_eq!(
+            skipped,
+            [
+                (
+                    4,
+                    "receiver class unknown, `Member` may not be an instruction"
+                ),
+                (
+                    5,
+                    "the object `cast<Instruction>(WL.pop_back_val())` cannot be evaluated again"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn replacements_are_tracked_around_the_helpers() {
+        let (code, report) = instrument_with_report(&pass(
+            "  for (Instruction &I : instructions(F)) {
+    ReplaceInstWithInst(&I, NewI);
+    changeToUnreachable(&I);
+    Changed |= llvm::replaceDominatedUsesWith(&I, V, DT, Root);
+    ReplaceInstWithValue(BI, V);
+    ReplaceInstWithValue(BI, UndefValue::get(BI->getType()));
+    ReplaceInstWithInst(cast<Instruction>(WL.pop_back_val()), NewI);
+  }",
+        ));
+        for hooks in [
+            "{ auto Replacement = hook::OnReplaceStart(&I, \"&I\"); ReplaceInstWithInst(&I, NewI); \
+             hook::OnReplace(Replacement, NewI, 5, \"NewI\"); }",
+            /* The `unreachable` taking the place of `I` is found after the call */
+            "{ auto Replacement1 = hook::OnReplaceStart(&I, \"&I\"); changeToUnreachable(&I); \
+             hook::OnReplace(Replacement1, nullptr, 6, \"\"); }",
+            "{ auto Replacement2 = hook::OnReplaceStart(&I, \"&I\"); \
+             Changed |= llvm::replaceDominatedUsesWith(&I, V, DT, Root); \
+             hook::OnReplace(Replacement2, V, 7, \"V\"); }",
+            "{ auto Replacement3 = hook::OnReplaceStart(&*BI, \"BI\"); ReplaceInstWithValue(BI, V); \
+             hook::OnReplace(Replacement3, V, 8, \"V\"); }",
+        ] {
+            assert!(code.contains(hooks), "{}", hooks);
         }
+
+        let skipped: Vec<(usize, &str)> = report
+            .sites
+            .iter()
+            .filter_map(|site| Some((site.line, site.skipped.as_deref()?)))
+            .collect();
+        assert_eq!(
+            skipped,
+            [
+                /* Read after the helper moves `BI` */
+                (
+                    9,
+                    "the destination `UndefValue::get(BI->getType())` cannot be evaluated again"
+                ),
+                (
+                    10,
+                    "the source `cast<Instruction>(WL.pop_back_val())` cannot be evaluated again"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn conflicts_are_reported_with_both_sites() {
+        let code = "void f() {\n  A(B(C));\n}\n";
+        let mut instrumenter = Instrumenter::new(&Catalog::load(None, None).unwrap());
+        instrumenter.target_file = "Pass.cpp".to_string();
+        let tree = instrumenter.parser.parse(code, None).unwrap();
+        let calls = get_children_of_kind(&tree.root_node(), "call_expression");
+        let [outer, inner] = calls[..] else {
+            panic!("expected two calls");
+        };
+
+        /* The inner call lies in the code the outer rewrite drops */
+        instrumenter.add_replace_edit(
+            &outer,
+            vec![Segment::Text("D()".to_string())],
+            outer.byte_range(),
+        );
+        instrumenter.add_wrap_edit(
+            &inner,
+            "E(".to_string(),
+            inner.byte_range(),
+            ")".to_string(),
+        );
+        let Err(conflict) = edit::apply(code, &instrumenter.edits) else {
+            panic!("expected a conflict");
+        };
+        let diag = instrumenter.conflict_error(&conflict, code);
+        assert_eq!(
+            diag.message,
+            "cannot compose the rewrite here, which lies in the code replaced by the rewrite for line 2:3"
+        );
+        let span = diag.span.unwrap();
+        assert_eq!((span.line, span.column), (2, 5));
+    }
+
+    #[test]
+    fn declarators_are_tracked_by_name() {
+        let code = instrument(&pass(
+            "  for (Instruction &I : instructions(F)) {
+    Instruction *A = I.clone(), *B = BinaryOperator::Create(Instruction::Add, X, Y);
+    if (auto *NewI = I.clone())
+      NewI->insertBefore(&I);
+    for (auto *PN = PHINode::Create(Ty, 2); PN; PN = nullptr)
+      PN->eraseFromParent();
+  }",
+        ));
+        /* One hook per declarator, after the declaration */
+        assert!(code.contains(
+            "*B = BinaryOperator::Create(Instruction::Add, X, Y); \
+             hook::OnClone(A, &I, 5, \"A\", \"I\"); hook::OnCreate(B, 5, \"B\");"
+        ));
+        /* In place, under the name declared */
+        assert!(code.contains("if (auto *NewI = hook::Cloned(I.clone(), &I, 6, \"NewI\", \"I\"))"));
+        assert!(
+            code.contains("for (auto *PN = hook::Created(PHINode::Create(Ty, 2), 8, \"PN\"); PN;")
+        );
+    }
+
+    #[test]
+    fn fresh_names_avoid_user_variables() {
+        let code = instrument(
+            "#include \"llvm/IR/Instructions.h\"
+
+PreservedAnalyses FooPass::run(Function &F, FunctionAnalysisManager &AM) {
+  int FinishGuard = 0;
+  Value *DebugLocSrc = nullptr;
+  for (Instruction &I : instructions(F)) {
+    I.replaceAllUsesWith(DebugLocSrc);
+    BranchInst::Create(I.getParent());
+  }
+  return PreservedAnalyses::none();
+}
+",
+        );
+        assert!(code.contains("auto FinishGuard1 = hook::OnStart(F, \"Pass.cpp\");"));
+        assert!(code.contains("Value *DebugLocSrc1 = &I; Value *DebugLocDst = DebugLocSrc;"));
+        assert!(code.contains("DebugLocSrc1->replaceAllUsesWith(DebugLocDst);"));
+        /* Nothing declared under the name of the loop variable */
+        assert!(code.contains("hook::Created(BranchInst::Create(I.getParent()), 8, \"\");"));
+        assert!(!code.contains("Instruction *I ="));
+    }
+
+    #[test]
+    fn fresh_names_are_unique_across_nested_scopes() {
+        let code = instrument(
+            "#include \"llvm/IR/Instructions.h\"
+
+static Value *make(Value *A, Value *B, bool C) {
+  if (C) {
+    Value *V = A;
+    return BinaryOperator::Create(Instruction::Add, V, B);
+  }
+  {
+    return BinaryOperator::Create(Instruction::Sub, A, B);
+  }
+}
+
+bool runOnFunction(Function &F) {
+  for (BasicBlock &BB : F) {
+    for (Instruction &I : BB) {
+      I.replaceAllUsesWith(UndefValue::get(I.getType()));
+    }
+    BB.getTerminator()->replaceAllUsesWith(nullptr);
+  }
+  return true;
+}
+",
+        );
+        assert!(code.contains("auto *V1 = BinaryOperator::Create(Instruction::Add, V, B);"));
+        assert!(code.contains("auto *V2 = BinaryOperator::Create(Instruction::Sub, A, B);"));
+        assert!(code.contains("Value *DebugLocSrc = &I;"));
+        assert!(code.contains("Value *DebugLocSrc1 = BB.getTerminator();"));
+        assert!(code.contains("Value *DebugLocDst1 = nullptr;"));
+    }
+
+    #[test]
+    fn fresh_names_avoid_lambda_variables() {
+        let code = instrument(
+            "#include \"llvm/IR/Instructions.h\"
+
+PreservedAnalyses FooPass::run(Function &F, FunctionAnalysisManager &AM) {
+  auto Make = [](Value *V) {
+    return BinaryOperator::Create(Instruction::Add, V, V);
+  };
+  auto Replace = [&](Instruction *DebugLocSrc, Value *FinishGuard) {
+    DebugLocSrc->replaceAllUsesWith(FinishGuard);
+  };
+  for (Instruction &I : instructions(F))
+    Replace(&I, Make(&I));
+  return PreservedAnalyses::none();
+}
+",
+        );
+        assert!(code.contains("auto FinishGuard1 = hook::OnStart(F, \"Pass.cpp\");"));
+        assert!(code.contains("auto *V1 = BinaryOperator::Create(Instruction::Add, V, V);"));
+        assert!(
+            code.contains("Value *DebugLocSrc1 = DebugLocSrc; Value *DebugLocDst = FinishGuard;")
+        );
     }
 }
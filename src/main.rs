@@ -1,79 +1,211 @@
 mod ast;
+mod catalog;
+mod config;
+mod diff;
+mod edit;
+mod error;
 mod hook;
 mod inst;
+mod manifest;
 mod r#match;
+mod report;
+mod strip;
+mod symbol;
+mod task;
 mod visit;
 
-use std::{
-    fs,
-    path::{Path, PathBuf},
-};
+use std::path::{Path, PathBuf};
 
-use clap::Parser;
-use colored::Colorize;
-use inst::Instrumenter;
+use catalog::Catalog;
+use clap::{Parser, Subcommand};
+use config::Config;
+use error::{Diagnostic, Status};
+use manifest::Manifest;
 
 #[derive(Parser)]
 #[command(name = "Pass Inst")]
 struct PassInst {
-    target: String,
+    #[command(subcommand)]
+    task: Task,
 
-    #[arg(short, long, default_value = "./instrumented")]
-    output: String,
+    /// Use this JSON or TOML config file instead of `config.json` and `metaloc.toml`
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+
+    /// Major version of the LLVM instrumented, selecting the APIs matched, the latest by default
+    #[arg(long, global = true)]
+    llvm_version: Option<u32>,
+}
+
+#[derive(Subcommand)]
+enum Task {
+    /// Copy the monitor library into the LLVM project
+    Setup,
+    /// Instrument the given pass file, or all the pass files under the given directory
+    Instrument {
+        target: PathBuf,
+
+        /// Write the instrumented files here, mirroring the layout under the target
+        #[arg(short, long, default_value = "./instrumented")]
+        output: PathBuf,
+
+        /// Instrument the files in place, recording them for `restore`
+        #[arg(long, conflicts_with = "output")]
+        in_place: bool,
+
+        /// Print the planned instrumentation as a unified diff without writing anything
+        #[arg(long, visible_alias = "diff")]
+        dry_run: bool,
+
+        /// Report every site found and skipped as `<file>.json` in the directory
+        #[arg(long)]
+        report: Option<PathBuf>,
+
+        /// Only instrument the files matching the glob, relative to the target directory
+        #[arg(long)]
+        include: Vec<String>,
+
+        /// Skip the files matching the glob, relative to the target directory
+        #[arg(long)]
+        exclude: Vec<String>,
+
+        /// What to do with the files that have been instrumented before
+        #[arg(long, value_enum, default_value_t = task::Existing::Skip)]
+        existing: task::Existing,
+
+        /// Instrument the files as one pass, whose entry may be in any of them, writing none if one fails
+        #[arg(long)]
+        project: bool,
+    },
+    /// Strip the hooks from the given instrumented file, or all the files under the given directory
+    Strip {
+        target: PathBuf,
+
+        /// Write the stripped files here, mirroring the layout under the target
+        #[arg(short, long, default_value = "./stripped")]
+        output: PathBuf,
+
+        /// Strip the files in place
+        #[arg(long, conflicts_with = "output")]
+        in_place: bool,
+
+        /// Fail on the files that do not instrument back to themselves after stripping
+        #[arg(long)]
+        check: bool,
+    },
+    /// Run the instrumented `opt` with the regression tests under the given path
+    Analyze { tests: PathBuf },
+    /// Put back the files instrumented in place
+    Restore {
+        /// Also overwrite the files edited after instrumentation
+        #[arg(long)]
+        force: bool,
+    },
+    /// Remove the monitor library and restore the files instrumented in place
+    Clean,
+    /// Inspect the catalog of the LLVM APIs matched
+    Catalog {
+        #[command(subcommand)]
+        action: CatalogAction,
+    },
+}
+
+#[derive(Subcommand)]
+enum CatalogAction {
+    /// Print the effective catalog for the LLVM version
+    Show,
 }
 
 fn main() {
     let pass_inst = PassInst::parse();
-    let path = Path::new(&pass_inst.target);
-    if !path.exists() {
-        eprintln!(
-            "{} {} does not exist!",
-            "Error".red().bold(),
-            path.display()
-        );
-        return;
-    }
-
-    let mut work_list: Vec<PathBuf> = vec![];
-
-    if path.is_file() {
-        if path.extension().unwrap() == "cpp" {
-            work_list.push(path.to_path_buf());
-        } else {
-            println!("{} only instrument xxx.cpp!", "Warning".yellow().bold());
-        }
-    }
-
-    if path.is_dir() {
-        for entry in path.read_dir().expect("Failed to read the directory!") {
-            if let Ok(e) = entry {
-                let file_path = e.path();
-                if file_path.is_file() {
-                    if file_path.extension().unwrap() == "cpp" {
-                        work_list.push(file_path.to_path_buf());
-                    } else {
-                        println!("{} only instrument xxx.cpp!", "Warning".yellow().bold());
-                    }
-                }
+    let config = Config::load(pass_inst.config.as_deref()).unwrap_or_else(|msg| exit(&msg));
+    let catalog = Catalog::load(
+        config.catalog.as_deref(),
+        pass_inst.llvm_version.or(config.llvm_version),
+    )
+    .unwrap_or_else(|msg| exit(&msg));
+
+    let status = match &pass_inst.task {
+        Task::Setup => task::setup(llvm_root(&config)),
+        Task::Instrument {
+            target,
+            output,
+            in_place,
+            dry_run,
+            report,
+            include,
+            exclude,
+            existing,
+            project,
+        } => {
+            let filter = task::FileFilter::new(include, exclude).unwrap_or_else(|msg| exit(&msg));
+            let report = report.as_deref();
+            if *dry_run {
+                task::instrument(
+                    target,
+                    task::Output::Diff,
+                    &filter,
+                    report,
+                    *existing,
+                    *project,
+                    &catalog,
+                )
+            } else if *in_place {
+                let mut manifest = load_manifest(&config);
+                task::instrument(
+                    target,
+                    task::Output::InPlace(&mut manifest),
+                    &filter,
+                    report,
+                    *existing,
+                    *project,
+                    &catalog,
+                )
+            } else {
+                task::instrument(
+                    target,
+                    task::Output::Dir(output),
+                    &filter,
+                    report,
+                    *existing,
+                    *project,
+                    &catalog,
+                )
             }
         }
-    }
-
-    if work_list.is_empty() {
-        println!("{} No file to instrument, exit.", "Finished".green().bold());
-    }
-
-    let mut instrumenter = Instrumenter::new();
-    let instrument = |path: &PathBuf| {
-        let absolute_path = path.canonicalize().unwrap();
-        let mut code = fs::read_to_string(absolute_path).unwrap();
-        let filename = path.file_name().unwrap().to_str().unwrap();
+        Task::Strip {
+            target,
+            output,
+            in_place,
+            check,
+        } => task::strip(
+            target,
+            (!in_place).then_some(output.as_path()),
+            *check,
+            &catalog,
+        ),
+        Task::Analyze { tests } => {
+            let opt = config.opt_binary().unwrap_or_else(|msg| exit(&msg));
+            task::analyze(opt, tests)
+        }
+        Task::Restore { force } => task::restore(&mut load_manifest(&config), *force),
+        Task::Clean => task::clean(llvm_root(&config), &mut load_manifest(&config)),
+        Task::Catalog {
+            action: CatalogAction::Show,
+        } => task::show_catalog(&catalog),
+    };
+    status.exit();
+}
 
-        instrumenter.instrument(filename, &mut code);
+fn llvm_root(config: &Config) -> &Path {
+    config.llvm_root().unwrap_or_else(|msg| exit(&msg))
+}
 
-        let output_filename = pass_inst.output.to_owned() + "/" + filename;
-        fs::write(output_filename, code).unwrap();
-    };
+fn load_manifest(config: &Config) -> Manifest {
+    Manifest::load(llvm_root(config)).unwrap_or_else(|msg| exit(&msg))
+}
 
-    work_list.iter().for_each(instrument);
+fn exit(msg: &str) -> ! {
+    Diagnostic::error(msg).emit();
+    Status::Invalid.exit();
 }
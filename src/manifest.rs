@@ -0,0 +1,265 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::error::Diagnostic;
+
+const MANIFEST_DIR: &str = ".passinst";
+const MANIFEST_FILE: &str = "manifest.json";
+
+/// A pass file instrumented in place
+#[derive(Serialize, Deserialize)]
+pub struct Entry {
+    pub path: PathBuf,
+    /// Hash of the original content, which also names the backup file
+    pub original_hash: String,
+    /// Hash of the instrumented content, used to detect later edits
+    pub instrumented_hash: String,
+}
+
+/// Records the files instrumented in place, stored next to the LLVM tree as
+/// `.passinst/manifest.json` along with the backups of the original files
+#[derive(Serialize, Deserialize, Default)]
+pub struct Manifest {
+    #[serde(skip)]
+    dir: PathBuf,
+    pub entries: Vec<Entry>,
+}
+
+pub enum RestoreStatus {
+    Restored,
+    /// The file has been restored by someone else
+    Unchanged,
+    /// The file has been edited after instrumentation
+    Modified,
+    /// The file no longer exists, its backup is kept
+    Missing,
+    Failed(Diagnostic),
+}
+
+impl Manifest {
+    /// Load the manifest of the LLVM tree rooted at `llvm`, or an empty one
+    pub fn load(llvm: &Path) -> Result<Self, String> {
+        let dir = llvm
+            .parent()
+            .map_or_else(|| llvm.to_path_buf(), Path::to_path_buf)
+            .join(MANIFEST_DIR);
+        let manifest_path = dir.join(MANIFEST_FILE);
+
+        let mut manifest = if manifest_path.exists() {
+            let content = fs::read_to_string(&manifest_path)
+                .map_err(|e| format!("Failed to read {}: {}", manifest_path.display(), e))?;
+            serde_json::from_str(&content)
+                .map_err(|e| format!("Failed to parse {}: {}", manifest_path.display(), e))?
+        } else {
+            Manifest::default()
+        };
+        manifest.dir = dir;
+        Ok(manifest)
+    }
+
+    /// Write the manifest back, removing it when no file is instrumented
+    pub fn save(&self) -> Result<(), String> {
+        let manifest_path = self.dir.join(MANIFEST_FILE);
+        if self.entries.is_empty() {
+            if self.dir.exists() {
+                fs::remove_dir_all(&self.dir)
+                    .map_err(|e| format!("Failed to remove {}: {}", self.dir.display(), e))?;
+            }
+            return Ok(());
+        }
+
+        fs::create_dir_all(&self.dir)
+            .map_err(|e| format!("Failed to create {}: {}", self.dir.display(), e))?;
+        let content = serde_json::to_string_pretty(self).unwrap();
+        fs::write(&manifest_path, content)
+            .map_err(|e| format!("Failed to write {}: {}", manifest_path.display(), e))
+    }
+
+    pub fn find(&self, path: &Path) -> Option<&Entry> {
+        self.entries.iter().find(|e| e.path == path)
+    }
+
+    /// The original content of a recorded file, read from its backup
+    pub fn original(&self, entry: &Entry) -> Result<String, String> {
+        let backup_path = self.dir.join(&entry.original_hash);
+        fs::read_to_string(&backup_path)
+            .map_err(|e| format!("Failed to read the backup {}: {}", backup_path.display(), e))
+    }
+
+    /// Back up the original content of `path` and record its instrumented content
+    pub fn record(
+        &mut self,
+        path: &Path,
+        original: &str,
+        instrumented: &str,
+    ) -> Result<(), String> {
+        let original_hash = hash(original);
+        let backup_path = self.dir.join(&original_hash);
+        fs::create_dir_all(&self.dir)
+            .map_err(|e| format!("Failed to create {}: {}", self.dir.display(), e))?;
+        fs::write(&backup_path, original)
+            .map_err(|e| format!("Failed to write {}: {}", backup_path.display(), e))?;
+
+        self.entries.retain(|e| e.path != path);
+        self.entries.push(Entry {
+            path: path.to_path_buf(),
+            original_hash,
+            instrumented_hash: hash(instrumented),
+        });
+        Ok(())
+    }
+
+    /// Put back the original content of every recorded file unless it was edited
+    /// after instrumentation, in which case the entry is kept for a later retry
+    pub fn restore(&mut self, force: bool) -> Vec<(PathBuf, RestoreStatus)> {
+        let mut results = vec![];
+        let mut done = vec![];
+        for entry in std::mem::take(&mut self.entries) {
+            let status = self.restore_entry(&entry, force);
+            let keep = matches!(
+                status,
+                RestoreStatus::Modified | RestoreStatus::Missing | RestoreStatus::Failed(_)
+            );
+            results.push((entry.path.clone(), status));
+            if keep {
+                self.entries.push(entry);
+            } else {
+                done.push(entry.original_hash);
+            }
+        }
+
+        /* Identical originals share one backup */
+        for original_hash in done {
+            if !self
+                .entries
+                .iter()
+                .any(|e| e.original_hash == original_hash)
+            {
+                let _ = fs::remove_file(self.dir.join(&original_hash));
+            }
+        }
+        results
+    }
+
+    fn restore_entry(&self, entry: &Entry, force: bool) -> RestoreStatus {
+        let Ok(current) = fs::read_to_string(&entry.path) else {
+            return RestoreStatus::Missing;
+        };
+        let current_hash = hash(&current);
+        if current_hash == entry.original_hash {
+            return RestoreStatus::Unchanged;
+        }
+        if current_hash != entry.instrumented_hash && !force {
+            return RestoreStatus::Modified;
+        }
+
+        let backup_path = self.dir.join(&entry.original_hash);
+        match fs::copy(&backup_path, &entry.path) {
+            Ok(_) => RestoreStatus::Restored,
+            Err(e) => RestoreStatus::Failed(Diagnostic::error(format!(
+                "failed to restore {}: {}",
+                entry.path.display(),
+                e
+            ))),
+        }
+    }
+}
+
+pub fn hash(content: &str) -> String {
+    Sha256::digest(content.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ORIGINAL: &str = "void f() {}\n";
+    const INSTRUMENTED: &str = "void f() { hook::OnStart(F, \"Pass.cpp\"); }\n";
+
+    /// A manifest recording one pass instrumented in place under a fresh directory
+    fn instrumented(name: &str) -> (PathBuf, Manifest) {
+        let root = std::env::temp_dir().join(format!("passinst-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let llvm = root.join("llvm");
+        fs::create_dir_all(&llvm).unwrap();
+        let pass = llvm.join("Pass.cpp");
+        fs::write(&pass, INSTRUMENTED).unwrap();
+
+        let mut manifest = Manifest::load(&llvm).unwrap();
+        manifest.record(&pass, ORIGINAL, INSTRUMENTED).unwrap();
+        (pass, manifest)
+    }
+
+    fn restore(manifest: &mut Manifest, force: bool) -> RestoreStatus {
+        let mut results = manifest.restore(force);
+        assert_eq!(results.len(), 1);
+        results.pop().unwrap().1
+    }
+
+    fn backup_exists(manifest: &Manifest) -> bool {
+        manifest.dir.join(hash(ORIGINAL)).exists()
+    }
+
+    fn clean_up(pass: &Path) {
+        fs::remove_dir_all(pass.parent().unwrap().parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn instrumented_files_are_restored() {
+        let (pass, mut manifest) = instrumented("restored");
+        assert!(matches!(
+            restore(&mut manifest, false),
+            RestoreStatus::Restored
+        ));
+        assert_eq!(fs::read_to_string(&pass).unwrap(), ORIGINAL);
+        assert!(manifest.entries.is_empty());
+        assert!(!backup_exists(&manifest));
+        clean_up(&pass);
+    }
+
+    #[test]
+    fn modified_files_are_restored_only_by_force() {
+        let (pass, mut manifest) = instrumented("modified");
+        fs::write(&pass, "void f() { edited(); }\n").unwrap();
+        assert!(matches!(
+            restore(&mut manifest, false),
+            RestoreStatus::Modified
+        ));
+        assert_eq!(
+            fs::read_to_string(&pass).unwrap(),
+            "void f() { edited(); }\n"
+        );
+        assert_eq!(manifest.entries.len(), 1);
+        assert!(backup_exists(&manifest));
+
+        assert!(matches!(
+            restore(&mut manifest, true),
+            RestoreStatus::Restored
+        ));
+        assert_eq!(fs::read_to_string(&pass).unwrap(), ORIGINAL);
+        assert!(manifest.entries.is_empty());
+        clean_up(&pass);
+    }
+
+    #[test]
+    fn missing_files_keep_their_backups() {
+        let (pass, mut manifest) = instrumented("missing");
+        fs::remove_file(&pass).unwrap();
+        assert!(matches!(
+            restore(&mut manifest, false),
+            RestoreStatus::Missing
+        ));
+        assert_eq!(manifest.entries.len(), 1);
+        assert!(backup_exists(&manifest));
+        assert_eq!(manifest.original(&manifest.entries[0]).unwrap(), ORIGINAL);
+        clean_up(&pass);
+    }
+}
@@ -1,65 +1,57 @@
-const CREATE_FN: [&str; 38] = [
-    "AllocaInst",
-    "BinaryOperator::Create", /* BinaryOperator */
-    "BranchInst::Create",     /* BranchInst */
-    "CallBase::Create",       /* CallBase */
-    "CallBase::addOperandBundle",
-    "CallBase::removeOperandBundle",
-    "CallBrInst::Create",         /* CallBrInst */
-    "CallInst::Create",           /* CallInst */
-    "CmpInst::Create",            /* CmpInst */
-    "FCmpInst",                   /* FCmpInst */
-    "ICmpInst",                   /* ICmpInst */
-    "ExtractElementInst::Create", /* ExtractElementInst */
-    "GetElementPtrInst::Create",  /* GetElementPtrInst */
-    "InsertElementInst::Create",  /* InsertElementInst */
-    "InsertValueInst::Create",    /* InsertValueInst */
-    "PHINode::Create",            /* PHINode */
-    "ReturnInst::Create",         /* ReturnInst */
-    "SelectInst::Create",         /* SelectInst */
-    "StoreInst",                  /* StoreInst */
-    "SwitchInst::Create",         /* SwitchInst */
-    "UnaryOperator::Create",
-    "LoadInst",
-    "FreezeInst",
-    "ExtractValueInst::Create",
-    "CastInst::Create", /* CastInst */
-    "AddrSpaceCastInst",
-    "BitCastInst",
-    "FPExtInst",
-    "FPToSIInst",
-    "FPToUIInst",
-    "FPTruncInst",
-    "IntToPtrInst",
-    "PtrToIntInst",
-    "SExtInst",
-    "SIToFPInst",
-    "TruncInst",
-    "UIToFPInst",
-    "ZExtInst",
-];
+use serde::{Deserialize, Serialize};
+use tree_sitter::Node;
+
+use crate::{ast::AstNode, catalog::Catalog};
 
-const CLONE_FN: [&str; 1] = ["clone"];
+/// Entries of the legacy passes and the helpers running a pass
+const ENTRY_FN: [&str; 4] = ["runOnFunction", "runOnLoop", "runOnModule", "runImpl"];
 
-const MOVE_FN: [&str; 3] = ["moveBefore", "moveBeforePreserving", "moveAfter"];
+const ENTRY_HELPER_FN: [&str; 1] = ["runImpl"];
 
-const USE_REPLACE_FN: [&str; 2] = ["replaceAllUsesWith", "replaceUsesOfWith"];
+/// Types of the IR units a pass runs on, which the monitor can start on
+pub const PASS_TARGET_TYPES: [&str; 5] = [
+    "Function",
+    "Loop",
+    "LoopNest",
+    "Module",
+    "LazyCallGraph::SCC",
+];
 
-// const INSERT_FN: [&str; 3] = ["insertBefore", "insertAfter", "insertInto"];
+/// Classes of the IR instructions not named `XxxInst` or `XxxIntrinsic`
+const INSTRUCTION_TYPES: [&str; 5] = [
+    "Instruction",
+    "PHINode",
+    "BinaryOperator",
+    "UnaryOperator",
+    "CallBase",
+];
 
-const REMOVE_FN: [&str; 1] = ["eraseFromParent"];
+/// Classes of the values that may be instructions, whose uses can be replaced
+const VALUE_TYPES: [&str; 3] = ["Value", "User", "Constant"];
 
-const DL_PRESERVE_FN: [&str; 1] = ["setDebugLoc"];
+/// Classes of the debug locations, whose static methods make new ones,
+/// e.g., `DebugLoc::getCompilerGenerated()` or `DILocation::get(...)`
+const LOCATION_TYPES: [&str; 2] = ["DebugLoc", "DILocation"];
 
-const DL_MERGE_FN: [&str; 1] = ["applyMergedLocation"];
+/// Methods merging the locations given, e.g., `DILocation::getMergedLocation(A, B)`
+const MERGED_LOCATION_FN: [&str; 2] = ["getMergedLocation", "getMergedLocations"];
 
-const DLDROP_FN: [&str; 2] = ["dropLocation", "updateLocationAfterHoist"];
+/// Methods making an empty location, e.g., `DebugLoc::getDropped()`
+const DROPPED_LOCATION_FN: [&str; 2] = ["getDropped", "getUnknown"];
 
-#[derive(PartialEq)]
+/// Kinds of the matched calls, which key the API catalog
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug, Serialize, Deserialize)]
 pub enum FnKind {
     Create,
+    BuilderCreate,
     Clone,
     Move,
+    Insert,
+    /// Helpers of Transforms/Utils replacing an instruction in one call,
+    /// e.g., `ReplaceInstWithInst(From, To)`
+    Replace,
+    /// `BasicBlock::splice`, moving the instructions of another block
+    Splice,
     UseReplace,
     Remove,
     DLPreserve,
@@ -67,45 +59,309 @@ pub enum FnKind {
     DLDrop,
 }
 
+impl FnKind {
+    /// Whether the kind is of the methods called on an IR unit, e.g., `I->clone()`
+    /// or `BB->splice(...)`, but not of a factory or a free helper
+    pub fn is_method(self) -> bool {
+        !matches!(
+            self,
+            FnKind::Create | FnKind::BuilderCreate | FnKind::Replace
+        )
+    }
+}
+
+/// An argument of a replacement helper, counted from the first one
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Operand {
+    /// The argument itself, e.g., `From` of `ReplaceInstWithInst(From, To)`
+    Arg(usize),
+    /// The instruction an iterator argument points to, e.g., `BI` of
+    /// `ReplaceInstWithValue(BI, V)`
+    Pointee(usize),
+}
+
+impl Operand {
+    pub fn index(self) -> usize {
+        match self {
+            Operand::Arg(i) | Operand::Pointee(i) => i,
+        }
+    }
+
+    /// A pointer to the instruction given by the argument
+    pub fn pointer(self, arg: &str) -> String {
+        match self {
+            Operand::Arg(_) => arg.to_string(),
+            Operand::Pointee(_) => format!("&*{}", arg),
+        }
+    }
+}
+
+/// How a replacement helper takes its source and destination from its arguments
+#[derive(Clone, Copy, Debug)]
+pub struct ReplaceModel {
+    pub src: Operand,
+    /// The value replacing the source, if given as an argument
+    pub dst: Option<Operand>,
+    /// Whether the destination takes the place of the source in its block
+    pub in_place: bool,
+}
+
+/// The model of a replacement helper called with `argc` arguments, for the
+/// overloads of `BasicBlockUtils.h` and `Local.h`
+pub fn replace_model(name: &str, argc: usize) -> Option<ReplaceModel> {
+    let (src, dst, in_place) = match (name, argc) {
+        /* ReplaceInstWithInst(From, To) or ReplaceInstWithInst(BB, BI, To) */
+        ("ReplaceInstWithInst", 2) => (Operand::Arg(0), Some(Operand::Arg(1)), true),
+        ("ReplaceInstWithInst", 3) => (Operand::Pointee(1), Some(Operand::Arg(2)), true),
+        /* ReplaceInstWithValue(BI, V) or ReplaceInstWithValue(BIL, BI, V) before LLVM 16 */
+        ("ReplaceInstWithValue", 2 | 3) => (
+            Operand::Pointee(argc - 2),
+            Some(Operand::Arg(argc - 1)),
+            false,
+        ),
+        ("changeToUnreachable" | "changeToCall", 1..) => (Operand::Arg(0), None, true),
+        /* replaceDominatedUsesWith(From, To, DT, Root) */
+        ("replaceDominatedUsesWith", 3..) => (Operand::Arg(0), Some(Operand::Arg(1)), false),
+        _ => return None,
+    };
+    Some(ReplaceModel { src, dst, in_place })
+}
+
 pub trait FnMatch {
-    fn get_fn_kind(&self) -> Option<FnKind>;
+    fn get_fn_kind(&self, catalog: &Catalog) -> Option<FnKind>;
     fn is_pass_entry(&self) -> bool;
+    fn is_entry_helper(&self) -> bool;
 }
 
 impl FnMatch for String {
-    fn get_fn_kind(&self) -> Option<FnKind> {
-        if CLONE_FN.contains(&self.as_str()) {
+    fn get_fn_kind(&self, catalog: &Catalog) -> Option<FnKind> {
+        let is = |kind| catalog.names(kind).contains(self);
+        if is(FnKind::Clone) {
             return Some(FnKind::Clone);
         }
-        if MOVE_FN.contains(&self.as_str()) {
+        if is(FnKind::Move) {
             return Some(FnKind::Move);
         }
-        for prefix in CREATE_FN {
-            if (prefix.contains("::") && self.starts_with(prefix)) || self.as_str() == prefix {
+        if is(FnKind::Insert) {
+            return Some(FnKind::Insert);
+        }
+        if is(FnKind::Replace) {
+            return Some(FnKind::Replace);
+        }
+        if is(FnKind::Splice) {
+            return Some(FnKind::Splice);
+        }
+        for prefix in catalog.names(FnKind::Create) {
+            if (prefix.contains("::") && self.starts_with(prefix.as_str())) || self == prefix {
                 return Some(FnKind::Create);
             }
         }
-        if USE_REPLACE_FN.contains(&self.as_str()) {
+        /* `IRBuilder` methods, e.g., `CreateAdd` but not `Create` */
+        if catalog.names(FnKind::BuilderCreate).iter().any(|prefix| {
+            self.strip_prefix(prefix.as_str())
+                .is_some_and(|rest| rest.starts_with(|c: char| c.is_ascii_uppercase()))
+        }) {
+            return Some(FnKind::BuilderCreate);
+        }
+        if is(FnKind::UseReplace) {
             return Some(FnKind::UseReplace);
         }
 
-        if DL_PRESERVE_FN.contains(&self.as_str()) {
+        if is(FnKind::DLPreserve) {
             return Some(FnKind::DLPreserve);
         }
-        if DL_MERGE_FN.contains(&self.as_str()) {
+        if is(FnKind::DLMerge) {
             return Some(FnKind::DLMerge);
         }
-        if DLDROP_FN.contains(&self.as_str()) {
+        if is(FnKind::DLDrop) {
             return Some(FnKind::DLDrop);
         }
 
-        if REMOVE_FN.contains(&self.as_str()) {
+        if is(FnKind::Remove) {
             return Some(FnKind::Remove);
         }
         None
     }
 
     fn is_pass_entry(&self) -> bool {
-        self.ends_with("Pass::run")
+        let name = self.rsplit("::").next().unwrap_or_default();
+        self.ends_with("Pass::run") || ENTRY_FN.contains(&name)
+    }
+
+    /// A helper like `runImpl`, which may take no IR unit to start on
+    fn is_entry_helper(&self) -> bool {
+        let name = self.rsplit("::").next().unwrap_or_default();
+        ENTRY_HELPER_FN.contains(&name)
+    }
+}
+
+/// Whether the class is an IR instruction, e.g., `LoadInst` or `IntrinsicInst`,
+/// but not `MCInst` or `VPInstruction`
+pub fn is_instruction_type(ty: &str) -> bool {
+    INSTRUCTION_TYPES.contains(&ty)
+        || ((ty.ends_with("Inst") || ty.ends_with("Intrinsic")) && !ty.starts_with("MC"))
+}
+
+/// Whether a value of the class may be an IR instruction
+pub fn is_value_type(ty: &str) -> bool {
+    is_instruction_type(ty) || VALUE_TYPES.contains(&ty)
+}
+
+/// How setting a debug location updates the one of the instruction, named
+/// after the `UpdateKind` of the monitor
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum UpdateKind {
+    Preserve,
+    Merge,
+    Drop,
+    Unknown,
+}
+
+/// The update made by setting the location, e.g., `Drop` for `I->setDebugLoc(DebugLoc())`.
+///
+/// A location taken from elsewhere, e.g., `OldI->getDebugLoc()` or `DL`, is preserved.
+pub fn location_update(loc: &Node, code: &str) -> UpdateKind {
+    match loc.kind() {
+        /* setDebugLoc({}) or setDebugLoc(nullptr) */
+        "initializer_list" if loc.named_child_count() == 0 => UpdateKind::Drop,
+        "null" => UpdateKind::Drop,
+        "parenthesized_expression" => match loc.named_child(0) {
+            Some(inner) => location_update(&inner, code),
+            None => UpdateKind::Unknown,
+        },
+        /* Cond ? DL : DebugLoc(), where the location preserved, if any, depends on `Cond` */
+        "conditional_expression" => {
+            let branches = ["consequence", "alternative"].map(|field| {
+                loc.child_by_field_name(field)
+                    .map(|b| location_update(&b, code))
+            });
+            match branches {
+                [Some(a), Some(b)] if a == b && a != UpdateKind::Preserve => a,
+                _ => UpdateKind::Unknown,
+            }
+        }
+        /* DebugLoc{} */
+        "compound_literal_expression" => {
+            let ty = loc.child_by_field_name("type").map(|ty| ty.to_source(code));
+            let empty = loc
+                .child_by_field_name("value")
+                .is_some_and(|value| value.named_child_count() == 0);
+            match ty.as_deref().map(|ty| ty.trim_start_matches("llvm::")) {
+                Some("DebugLoc") if empty => UpdateKind::Drop,
+                _ => UpdateKind::Unknown,
+            }
+        }
+        "call_expression" => {
+            let (Some(function), Some(arguments)) = (
+                loc.child_by_field_name("function"),
+                loc.child_by_field_name("arguments"),
+            ) else {
+                return UpdateKind::Unknown;
+            };
+            let name = function.to_source(code);
+            let name = name.trim_start_matches("llvm::");
+            let (class, method) = name.rsplit_once("::").unwrap_or_default();
+            let method = match function.kind() {
+                "field_expression" => function
+                    .child_by_field_name("field")
+                    .map(|field| field.to_source(code))
+                    .unwrap_or_default(),
+                _ => method.to_string(),
+            };
+            if MERGED_LOCATION_FN.contains(&method.as_str()) {
+                return UpdateKind::Merge;
+            }
+            match name {
+                /* DebugLoc() or DebugLoc(N) wrapping another location */
+                "DebugLoc" => match arguments.named_child(0) {
+                    None => UpdateKind::Drop,
+                    Some(inner) => location_update(&inner, code),
+                },
+                _ if class == "DebugLoc" && DROPPED_LOCATION_FN.contains(&method.as_str()) => {
+                    UpdateKind::Drop
+                }
+                _ if LOCATION_TYPES.contains(&class) => UpdateKind::Unknown,
+                _ => UpdateKind::Preserve,
+            }
+        }
+        _ => UpdateKind::Preserve,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tree_sitter::Parser;
+
+    use super::*;
+    use crate::visit::get_children_of_kind;
+
+    fn update_of(loc: &str) -> UpdateKind {
+        let code = format!("void f() {{ I->setDebugLoc({}); }}", loc);
+        let mut parser = Parser::new();
+        parser.set_language(&tree_sitter_cpp::language()).unwrap();
+        let tree = parser.parse(&code, None).unwrap();
+        let call = get_children_of_kind(&tree.root_node(), "call_expression")
+            .into_iter()
+            .find(|call| call.to_source(&code).starts_with("I->setDebugLoc"))
+            .unwrap();
+        let arguments = call.child_by_field_name("arguments").unwrap();
+        location_update(&arguments.named_child(0).unwrap(), &code)
+    }
+
+    #[test]
+    fn replace_models() {
+        let model = |name: &str, argc| {
+            replace_model(name, argc).map(|model| (model.src, model.dst, model.in_place))
+        };
+        assert_eq!(
+            model("ReplaceInstWithInst", 2),
+            Some((Operand::Arg(0), Some(Operand::Arg(1)), true))
+        );
+        assert_eq!(
+            model("ReplaceInstWithInst", 3),
+            Some((Operand::Pointee(1), Some(Operand::Arg(2)), true))
+        );
+        assert_eq!(
+            model("ReplaceInstWithValue", 2),
+            Some((Operand::Pointee(0), Some(Operand::Arg(1)), false))
+        );
+        assert_eq!(
+            model("ReplaceInstWithValue", 3),
+            Some((Operand::Pointee(1), Some(Operand::Arg(2)), false))
+        );
+        assert_eq!(
+            model("changeToUnreachable", 1),
+            Some((Operand::Arg(0), None, true))
+        );
+        assert_eq!(
+            model("replaceDominatedUsesWith", 4),
+            Some((Operand::Arg(0), Some(Operand::Arg(1)), false))
+        );
+        assert_eq!(model("replaceDominatedUsesWith", 2), None);
+        assert_eq!(model("changeToUnreachable", 0), None);
+    }
+
+    #[test]
+    fn location_updates() {
+        for (loc, kind) in [
+            ("DebugLoc(N)", UpdateKind::Preserve),
+            ("std::move(DL)", UpdateKind::Preserve),
+            ("I->getDebugLoc()", UpdateKind::Preserve),
+            ("DebugLoc()", UpdateKind::Drop),
+            ("{}", UpdateKind::Drop),
+            ("nullptr", UpdateKind::Drop),
+            ("DebugLoc::getDropped()", UpdateKind::Drop),
+            ("C ? DebugLoc() : nullptr", UpdateKind::Drop),
+            (
+                "DILocation::getMergedLocation(A->getDebugLoc(), B->getDebugLoc())",
+                UpdateKind::Merge,
+            ),
+            ("C ? A : B", UpdateKind::Unknown),
+            ("C ? DL : DebugLoc()", UpdateKind::Unknown),
+            ("DILocation::get(Ctx, 0, 0, Scope)", UpdateKind::Unknown),
+            ("DebugLoc::getCompilerGenerated()", UpdateKind::Unknown),
+        ] {
+            assert_eq!(update_of(loc), kind, "{}", loc);
+        }
     }
 }
@@ -0,0 +1,50 @@
+use serde::Serialize;
+
+use crate::{error::Diagnostic, r#match::FnKind};
+
+/// A matched call or `new` expression
+#[derive(Serialize)]
+pub struct Site {
+    pub kind: FnKind,
+    pub line: usize,
+    pub column: usize,
+    pub source: String,
+    /// Names of the instructions involved, e.g., the new and the old one for a clone
+    pub vars: Vec<String>,
+    /// The generated hook, if the site is instrumented
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hook: Option<String>,
+    /// Why the site is not instrumented
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub skipped: Option<String>,
+}
+
+/// All the sites found in one pass file
+#[derive(Serialize)]
+pub struct Report {
+    pub file: String,
+    /// Number of the pass entries, which start the analysis
+    pub entries: usize,
+    pub instrumented: usize,
+    pub skipped: usize,
+    pub sites: Vec<Site>,
+    pub warnings: Vec<Diagnostic>,
+}
+
+impl Report {
+    pub fn new(file: &str, entries: usize, sites: Vec<Site>, warnings: Vec<Diagnostic>) -> Self {
+        let skipped = sites.iter().filter(|s| s.skipped.is_some()).count();
+        Self {
+            file: file.to_owned(),
+            entries,
+            instrumented: sites.len() - skipped,
+            skipped,
+            sites,
+            warnings,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+}
@@ -0,0 +1,456 @@
+use std::ops::Range;
+
+use tree_sitter::{Node, Parser};
+
+use crate::{ast::AstNode, error::Diagnostic, hook, visit::get_children_of_kind};
+
+/// Reverses the code generated by `Instrumenter`
+pub struct Stripper {
+    parser: Parser,
+}
+
+impl Stripper {
+    pub fn new() -> Self {
+        let mut parser = Parser::new();
+        let grammar = tree_sitter_cpp::language();
+        parser
+            .set_language(&grammar)
+            .expect("Error loading grammar");
+
+        Self { parser }
+    }
+
+    /// Strip the monitor include and the hooks from the code, returning the number
+    /// of the generated constructs removed.
+    ///
+    /// The code is left untouched if some hook is not generated by us.
+    pub fn strip(&mut self, filename: &str, code: &mut String) -> Result<usize, Diagnostic> {
+        let mut stripped_code = code.clone();
+        let mut stripped = 0;
+
+        /* Constructs may nest, e.g., a hook block in another one,
+         * so strip the outermost ones until none is left */
+        loop {
+            let tree = self
+                .parser
+                .parse(&stripped_code, None)
+                .ok_or_else(|| Diagnostic::error(format!("failed to parse {}", filename)))?;
+            let root_node = tree.root_node();
+
+            let mut edits = vec![];
+            let mut unknown = None;
+            for include in get_children_of_kind(&root_node, "preproc_include") {
+                if let Some(range) = header_include(&include, &stripped_code) {
+                    edits.push((range, String::new()));
+                }
+            }
+            for call in get_children_of_kind(&root_node, "call_expression") {
+                if !is_hook_call(&call, &stripped_code) {
+                    continue;
+                }
+                match reverse(&call, &stripped_code) {
+                    Some(edit) => edits.push(edit),
+                    None => {
+                        unknown.get_or_insert(call);
+                    }
+                }
+            }
+
+            /* Keep the outermost of the overlapping edits, the inner ones are left to the next round */
+            edits.sort_by_key(|(range, _)| (range.start, std::cmp::Reverse(range.end)));
+            let mut outermost: Vec<(Range<usize>, String)> = vec![];
+            for edit in edits {
+                if outermost
+                    .last()
+                    .is_none_or(|(range, _)| range.end <= edit.0.start)
+                {
+                    outermost.push(edit);
+                }
+            }
+
+            if outermost.is_empty() {
+                if let Some(call) = unknown {
+                    return Err(Diagnostic::error(
+                        "unrecognized hook call, remove it by hand and strip again",
+                    )
+                    .at(&call, filename, &stripped_code));
+                }
+                break;
+            }
+
+            stripped += outermost.len();
+            for (range, original) in outermost.into_iter().rev() {
+                stripped_code.replace_range(range, &original);
+            }
+        }
+
+        *code = stripped_code;
+        Ok(stripped)
+    }
+}
+
+/// The range of the monitor include, either on a blank line of its own or before
+/// a line comment
+fn header_include(include: &Node, code: &str) -> Option<Range<usize>> {
+    let start = include.start_byte();
+    let include_line = hook::HEADER_INCLUDE.trim_end();
+    if !code[start..].starts_with(include_line) {
+        return None;
+    }
+
+    /* Put before a line comment, i.e., `#include "...DLMonitor.h" //===-- ...` */
+    let end = start + include_line.len();
+    if code[end..].starts_with(" //") {
+        Some(start..end + 1)
+    } else {
+        Some(start..end)
+    }
+}
+
+fn is_hook_call(call: &Node, code: &str) -> bool {
+    call.child_by_field_name("function")
+        .is_some_and(|function| function.to_source(code).starts_with(hook::NAMESPACE))
+}
+
+/// The range generated for the hook call and the original code it replaces
+fn reverse(call: &Node, code: &str) -> Option<(Range<usize>, String)> {
+    let function = call.child_by_field_name("function")?.to_source(code);
+
+    /* PN->addIncoming(hook::Created(BinaryOperator::Create(...), 12, ""), BB) */
+    if matches!(
+        function.as_str(),
+        "hook::Created" | "hook::BuilderCreated" | "hook::Cloned"
+    ) {
+        return Some((call.byte_range(), first_arg(call, code)?));
+    }
+
+    let mut stmt = call.parent()?;
+
+    /* auto FinishGuard = hook::OnStart(F, "Pass.cpp"); */
+    if function == "hook::OnStart" {
+        if stmt.kind() == "init_declarator" {
+            stmt = stmt.parent()?;
+            let guard = stmt.child_by_field_name("type")?;
+            if stmt.kind() != "declaration" || guard.to_raw(code) != "auto" {
+                return None;
+            }
+        } else if stmt.kind() != "expression_statement" {
+            return None;
+        }
+        /* Put right after the brace of the entry, i.e., `{ auto FinishGuard = ...;` */
+        let mut start = stmt.start_byte();
+        if code[..start].ends_with("{ ") {
+            start -= 1;
+        }
+        return Some((start..stmt.end_byte(), String::new()));
+    }
+    if stmt.kind() != "expression_statement" {
+        return None;
+    }
+
+    let parent = stmt.parent()?;
+    if parent.kind() == "compound_statement" {
+        if let Some(original) = unwrap_block(&parent, &stmt, code) {
+            return Some((parent.start_byte()..parent.end_byte(), original));
+        }
+    }
+
+    /* Appended to a declaration, i.e., `auto *NI = OI->clone(); hook::OnClone(...);` */
+    if !matches!(
+        function.as_str(),
+        "hook::OnCreate" | "hook::OnBuilderCreate" | "hook::OnClone"
+    ) {
+        return None;
+    }
+    let decl = stmt.prev_named_sibling()?;
+    if decl.kind() != "declaration" || &code[decl.end_byte()..stmt.start_byte()] != " " {
+        return None;
+    }
+    /* A rewritten expression statement, followed by its own semicolon, i.e.,
+     * `Instruction *I = BranchInst::Create(...); hook::OnCreate(I, ...);;`,
+     * as generated before the discarded creations were wrapped in place */
+    if code[stmt.end_byte()..].starts_with(';') {
+        let (ty, name, value) = generated_decl(&decl, code)?;
+        if ty == "Instruction" && first_arg(call, code)? == name {
+            return Some((decl.start_byte()..stmt.end_byte(), value));
+        }
+    }
+    Some((decl.end_byte()..stmt.end_byte(), String::new()))
+}
+
+/// The original code of a block generated around a hook
+fn unwrap_block(block: &Node, hook_stmt: &Node, code: &str) -> Option<String> {
+    let text = block.to_raw(code);
+    let hook_text = hook_stmt.to_raw(code);
+    let stmts: Vec<Node> = block
+        .named_children(&mut block.walk())
+        .filter(|node| node.kind() != "comment")
+        .collect();
+
+    match stmts.as_slice() {
+        /* { hook::OnRemove(...); I->eraseFromParent();}, or { hook::OnFinish(); return ...; }
+        generated by earlier versions before the guard of OnStart */
+        [first, inner] if first == hook_stmt => {
+            let inner_text = inner.to_raw(code);
+            (text == format!("{{ {} {} }}", hook_text, inner_text)
+                || text == format!("{{ {} {}}}", hook_text, inner_text))
+            .then_some(inner_text)
+        }
+        /* { I->moveBefore(...); hook::OnMove(...); } */
+        [inner, last] if last == hook_stmt => {
+            let inner_text = inner.to_raw(code);
+            (text == format!("{{ {} {} }}", inner_text, hook_text)).then_some(inner_text)
+        }
+        /* { auto *V = BinaryOperator::Create(...); hook::OnCreate(V, ...); return V; } */
+        [decl, middle, ret] if middle == hook_stmt => {
+            let (ty, name, value) = generated_decl(decl, code)?;
+            let ret_text = ret.to_raw(code);
+            let expected = format!("{{ {} {} {} }}", decl.to_raw(code), hook_text, ret_text);
+            if !matches!(ty.as_str(), "auto" | "Value")
+                || ret.kind() != "return_statement"
+                || text != expected
+            {
+                return None;
+            }
+            /* The temporary takes the place of the expression returned */
+            let temp = ret
+                .named_children(&mut ret.walk())
+                .find(|node| node.kind() != "comment")
+                .filter(|temp| temp.kind() == "identifier" && temp.to_raw(code) == name)?;
+            let offset = temp.start_byte() - ret.start_byte();
+            Some(format!(
+                "{}{}{}",
+                &ret_text[..offset],
+                value,
+                &ret_text[offset + name.len()..]
+            ))
+        }
+        /* { auto Replacement = hook::OnReplaceStart(...); ReplaceInstWithInst(...); hook::OnReplace(Replacement, ...); } */
+        [start, inner, last] if last == hook_stmt => {
+            let inner_text = inner.to_raw(code);
+            let expected = format!("{{ {} {} {} }}", start.to_raw(code), inner_text, hook_text);
+            let name = hook_start_decl(start, code)?;
+            let call = hook_stmt.named_child(0)?;
+            (text == expected && first_arg(&call, code)? == name).then_some(inner_text)
+        }
+        /* { Value *Src = ...; Value *Dst = ...; Src->replaceAllUsesWith(Dst); hook::OnUseReplace(...); } */
+        [src_decl, dst_decl, replace, last] if last == hook_stmt => {
+            let expected = format!(
+                "{{ {} {} {} {} }}",
+                src_decl.to_raw(code),
+                dst_decl.to_raw(code),
+                replace.to_raw(code),
+                hook_text
+            );
+            if text != expected {
+                return None;
+            }
+            unwrap_use_replace(src_decl, dst_decl, replace, code)
+        }
+        _ => None,
+    }
+}
+
+/// Put the values of the temporaries back into the replacement call
+fn unwrap_use_replace(
+    src_decl: &Node,
+    dst_decl: &Node,
+    replace: &Node,
+    code: &str,
+) -> Option<String> {
+    let (src_ty, src_name, src_value) = generated_decl(src_decl, code)?;
+    let (dst_ty, dst_name, dst_value) = generated_decl(dst_decl, code)?;
+    if src_ty != "Value" || dst_ty != "Value" {
+        return None;
+    }
+
+    let call = replace.named_child(0)?;
+    let callee = call.child_by_field_name("function")?;
+    if callee.kind() != "field_expression" {
+        return None;
+    }
+    let object = callee.child_by_field_name("argument")?.to_raw(code);
+    let operator = callee.child(1)?.to_raw(code);
+    let method = callee.child_by_field_name("field")?.to_raw(code);
+    let args: Vec<String> = call
+        .child_by_field_name("arguments")?
+        .named_children(&mut call.walk())
+        .map(|arg| arg.to_raw(code))
+        .collect();
+
+    match method.as_str() {
+        "replaceAllUsesWith"
+            if object == src_name && operator == "->" && args == [dst_name.as_str()] =>
+        {
+            Some(match src_value.strip_prefix('&') {
+                Some(object) => format!("{}.replaceAllUsesWith({});", object, dst_value),
+                None => format!("{}->replaceAllUsesWith({});", src_value, dst_value),
+            })
+        }
+        "replaceUsesOfWith" if args == [src_name.as_str(), dst_name.as_str()] => Some(format!(
+            "{}{}replaceUsesOfWith({}, {});",
+            object, operator, src_value, dst_value
+        )),
+        _ => None,
+    }
+}
+
+/// The type, name and value of a generated temporary like `Value *V = ...;`
+fn generated_decl(decl: &Node, code: &str) -> Option<(String, String, String)> {
+    if decl.kind() != "declaration" || decl.named_child_count() != 2 {
+        return None;
+    }
+    let ty = decl.child_by_field_name("type")?.to_raw(code);
+    let init = decl.child_by_field_name("declarator")?;
+    let pointer = init.child_by_field_name("declarator")?;
+    if init.kind() != "init_declarator" || pointer.kind() != "pointer_declarator" {
+        return None;
+    }
+    let name = pointer.child_by_field_name("declarator")?.to_raw(code);
+    let value = init.child_by_field_name("value")?.to_raw(code);
+    (decl.to_raw(code) == format!("{} *{} = {};", ty, name, value)).then_some((ty, name, value))
+}
+
+/// The name declared by `auto Name = hook::OnXxxStart(...);`, capturing the state
+/// before a call for the hook after it
+fn hook_start_decl(decl: &Node, code: &str) -> Option<String> {
+    if decl.kind() != "declaration" || decl.child_by_field_name("type")?.to_raw(code) != "auto" {
+        return None;
+    }
+    let init = decl.child_by_field_name("declarator")?;
+    let value = init.child_by_field_name("value")?;
+    if init.kind() != "init_declarator" || value.kind() != "call_expression" {
+        return None;
+    }
+    let function = value.child_by_field_name("function")?.to_source(code);
+    (function.starts_with(hook::NAMESPACE) && function.ends_with("Start"))
+        .then(|| {
+            init.child_by_field_name("declarator")
+                .map(|name| name.to_raw(code))
+        })
+        .flatten()
+}
+
+fn first_arg(call: &Node, code: &str) -> Option<String> {
+    let arguments = call.child_by_field_name("arguments")?;
+    arguments.named_child(0).map(|arg| arg.to_raw(code))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{catalog::Catalog, inst::Instrumenter};
+
+    /// Instrument the pass, strip it and check the original comes back,
+    /// returning the instrumented code
+    fn round_trip(original: &str) -> String {
+        let catalog = Catalog::load(None, None).unwrap();
+        let mut instrumented = original.to_string();
+        Instrumenter::new(&catalog)
+            .instrument("Pass.cpp", &mut instrumented)
+            .unwrap_or_else(|diag| panic!("{}", diag.message));
+        assert_ne!(instrumented, original);
+
+        let mut stripped = instrumented.clone();
+        Stripper::new()
+            .strip("Pass.cpp", &mut stripped)
+            .unwrap_or_else(|diag| panic!("{}", diag.message));
+        assert_eq!(stripped, original);
+        instrumented
+    }
+
+    fn pass(body: &str) -> String {
+        format!(
+            "#include \"llvm/IR/Instructions.h\"\n\n\
+             using namespace llvm;\n\n\
+             PreservedAnalyses FooPass::run(Function &F, FunctionAnalysisManager &AM) {{\n\
+             {}\n  return PreservedAnalyses::none();\n}}\n",
+            body
+        )
+    }
+
+    #[test]
+    fn round_trip_statements() {
+        round_trip(&pass(
+            "  for (Instruction &I : instructions(F)) {
+    Instruction *NewI = I.clone();
+    NewI->insertBefore(&I);
+    NewI->setDebugLoc(I.getDebugLoc());
+    I.replaceAllUsesWith(NewI);
+    I.eraseFromParent();
+  }",
+        ));
+    }
+
+    #[test]
+    fn round_trip_unbraced_bodies() {
+        let instrumented = round_trip(&pass(
+            "  for (BasicBlock &BB : F)
+    BranchInst::Create(&BB);
+  if (auto *I = dyn_cast<Instruction>(V))
+    I->eraseFromParent();
+  else
+    BranchInst::Create(BB);
+  while (!WL.empty())
+    cast<Instruction>(WL.back())->moveBefore(Pos);",
+        ));
+        assert!(instrumented.contains("for (BasicBlock &BB : F)\n    hook::Created("));
+        assert!(instrumented.contains("else\n    hook::Created("));
+    }
+
+    #[test]
+    fn round_trip_nested_sites() {
+        round_trip(&pass(
+            "  PN->addIncoming(BinaryOperator::Create(Instruction::Add, A, B), BB);
+  auto *Sel = SelectInst::Create(C, cast<Instruction>(I->clone()), Other);
+  if (auto *NewI = I->clone())
+    ReplaceInstWithInst(I, NewI);",
+        ));
+    }
+
+    #[test]
+    fn round_trip_assignments_apart_from_semicolons() {
+        let instrumented = round_trip(&pass(
+            "  X = BinaryOperator::Create(Instruction::Add, X, Y) ;
+  NewI = cast<Instruction>(V)->clone() /* copy */;",
+        ));
+        assert!(instrumented
+            .contains("{ X = BinaryOperator::Create(Instruction::Add, X, Y) ; hook::OnCreate(X, "));
+        assert!(instrumented
+            .contains("{ NewI = cast<Instruction>(V)->clone() /* copy */; hook::OnClone(NewI, "));
+    }
+
+    #[test]
+    fn round_trip_quoted_names() {
+        let instrumented = round_trip(&pass(
+            "  cast<Instruction>(Map[\"x\"])->eraseFromParent();
+  cast<Instruction>(Map[\"y\"])->setDebugLoc(DebugLoc());",
+        ));
+        assert!(instrumented.contains(r#""cast<Instruction>(Map[\"x\"])""#));
+        assert!(instrumented.contains("::UpdateKind::Drop"));
+    }
+
+    #[test]
+    fn round_trip_returns() {
+        let instrumented = round_trip(
+            "#include \"llvm/IR/Instructions.h\"\n\n\
+             static Value *create(Value *A, Value *B) {\n\
+             \x20 return BinaryOperator::Create(Instruction::Add, A, B);\n\
+             }\n\n\
+             static Instruction *copy(Instruction *I) {\n\
+             \x20 return cast<Instruction>(I->clone());\n\
+             }\n\n\
+             bool runOnFunction(Function &F) {\n\
+             \x20 if (F.empty())\n\
+             \x20   return false;\n\
+             \x20 return true;\n\
+             }\n",
+        );
+        assert!(instrumented.contains(
+            "{ auto *V = BinaryOperator::Create(Instruction::Add, A, B); hook::OnCreate(V, 4, \"\"); return V; }"
+        ));
+        /* Nested in the expression returned, tracked in place */
+        assert!(instrumented
+            .contains("return cast<Instruction>(hook::Cloned(I->clone(), I, 8, \"\", \"I\"));"));
+    }
+}
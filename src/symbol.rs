@@ -0,0 +1,310 @@
+use std::ops::Range;
+
+use tree_sitter::Node;
+
+use crate::{
+    ast::AstNode,
+    catalog::Catalog,
+    r#match::{is_instruction_type, FnKind, FnMatch},
+    visit::get_children_of_kind,
+};
+
+/// Nodes opening a scope for the names declared in them
+const SCOPE_KINDS: [&str; 9] = [
+    "compound_statement",
+    "for_statement",
+    "for_range_loop",
+    "if_statement",
+    "while_statement",
+    "switch_statement",
+    "catch_clause",
+    "lambda_expression",
+    "function_definition",
+];
+
+/// Casts whose template argument is the type of the result
+pub const CAST_FN: [&str; 6] = [
+    "cast",
+    "dyn_cast",
+    "cast_or_null",
+    "dyn_cast_or_null",
+    "cast_if_present",
+    "dyn_cast_if_present",
+];
+
+/// Accessors returning an instruction whatever they are called on
+const INSTRUCTION_ACCESSOR_FN: [&str; 4] = [
+    "getTerminator",
+    "getFirstNonPHI",
+    "getFirstNonPHIOrDbg",
+    "getFirstNonPHIOrDbgOrLifetime",
+];
+
+/// `IRBuilder` methods always creating an instruction of the class, which the
+/// others may fold into a constant
+const BUILDER_INSTRUCTION_FN: [(&str, &str); 17] = [
+    ("CreateCall", "CallInst"),
+    ("CreateIntrinsic", "CallInst"),
+    ("CreateInvoke", "InvokeInst"),
+    ("CreateLoad", "LoadInst"),
+    ("CreateAlignedLoad", "LoadInst"),
+    ("CreateStore", "StoreInst"),
+    ("CreateAlignedStore", "StoreInst"),
+    ("CreateAlloca", "AllocaInst"),
+    ("CreateBr", "BranchInst"),
+    ("CreateCondBr", "BranchInst"),
+    ("CreateRet", "ReturnInst"),
+    ("CreateRetVoid", "ReturnInst"),
+    ("CreateSwitch", "SwitchInst"),
+    ("CreateUnreachable", "UnreachableInst"),
+    ("CreatePHI", "PHINode"),
+    ("CreateFence", "FenceInst"),
+    ("CreateAtomicRMW", "AtomicRMWInst"),
+];
+
+/// Adaptors iterating over the range given as their first argument
+const RANGE_ADAPTOR_FN: [&str; 4] = ["make_early_inc_range", "reverse", "drop_begin", "drop_end"];
+
+/// A variable or parameter declared in the function
+struct Symbol {
+    name: String,
+    /// Class of the variable, e.g., `PHINode` for `PHINode *PN`, if it can be resolved
+    ty: Option<String>,
+    /// Class of the elements of a container, e.g., `Instruction` for
+    /// `SmallVector<Instruction *, 4> ToErase`
+    elem: Option<String>,
+    /// Where it is declared, which the uses follow
+    pos: usize,
+    /// Where the name is visible
+    scope: Range<usize>,
+}
+
+/// The declared types of the names in one function, including its lambdas
+#[derive(Default)]
+pub struct SymbolTable {
+    symbols: Vec<Symbol>,
+}
+
+impl SymbolTable {
+    pub fn new(fn_def: &Node, code: &str, catalog: &Catalog) -> Self {
+        let mut table = Self { symbols: vec![] };
+
+        let mut decls: Vec<Node> = [
+            "parameter_declaration",
+            "optional_parameter_declaration",
+            "declaration",
+            "for_range_loop",
+        ]
+        .iter()
+        .flat_map(|kind| get_children_of_kind(fn_def, kind))
+        .collect();
+        /* Resolve `auto` from the names declared before */
+        decls.sort_by_key(|decl| decl.start_byte());
+        for decl in decls {
+            table.declare(&decl, code, catalog);
+        }
+        table
+    }
+
+    /// The class of an expression, e.g., `PHINode` for `PN`, `*PN` or `cast<PHINode>(V)`
+    pub fn type_of(&self, expr: &Node, code: &str, catalog: &Catalog) -> Option<String> {
+        match expr.kind() {
+            "identifier" => self.lookup(expr, code)?.ty.clone(),
+            "pointer_expression" | "parenthesized_expression" => {
+                self.type_of(&expr.named_child(0)?, code, catalog)
+            }
+            "call_expression" => self.type_of_call(expr, code, catalog),
+            "new_expression" => Some(class_name(&expr.child_by_field_name("type")?, code)),
+            _ => None,
+        }
+    }
+
+    /// The declaration of the name visible where it is used
+    fn lookup(&self, ident: &Node, code: &str) -> Option<&Symbol> {
+        let name = ident.to_source(code);
+        self.symbols
+            .iter()
+            .filter(|s| {
+                s.name == name
+                    && s.pos < ident.start_byte()
+                    && s.scope.contains(&ident.start_byte())
+            })
+            .max_by_key(|s| s.pos)
+    }
+
+    fn declare(&mut self, decl: &Node, code: &str, catalog: &Catalog) {
+        let Some(ty) = decl.child_by_field_name("type") else {
+            return;
+        };
+        let scope = match decl.kind() {
+            "for_range_loop" => decl.byte_range(),
+            _ => scope_of(decl),
+        };
+        let is_auto = ty.kind() == "placeholder_type_specifier";
+        let elem = (ty.kind() == "template_type")
+            .then(|| {
+                let argument = ty
+                    .child_by_field_name("arguments")?
+                    .named_child(0)?
+                    .child_by_field_name("type")?;
+                Some(class_name(&argument, code))
+            })
+            .flatten();
+
+        let mut cursor = decl.walk();
+        let declarators: Vec<Node> = decl
+            .children_by_field_name("declarator", &mut cursor)
+            .collect();
+        for declarator in declarators {
+            let Some(name) = declarator_name(&declarator) else {
+                continue;
+            };
+            let resolved = if !is_auto {
+                Some(class_name(&ty, code))
+            } else if decl.kind() == "for_range_loop" {
+                decl.child_by_field_name("right")
+                    .and_then(|range| self.element_type(&range, code, catalog))
+            } else {
+                /* `auto *I = ...` in a declaration, or in a condition with no init declarator */
+                declarator
+                    .child_by_field_name("value")
+                    .or_else(|| decl.child_by_field_name("value"))
+                    .and_then(|value| self.type_of(&value, code, catalog))
+            };
+            self.symbols.push(Symbol {
+                name: name.to_source(code),
+                ty: resolved,
+                elem: elem.clone(),
+                pos: declarator.start_byte(),
+                scope: scope.clone(),
+            });
+        }
+    }
+
+    fn type_of_call(&self, call: &Node, code: &str, catalog: &Catalog) -> Option<String> {
+        let function = call.child_by_field_name("function")?;
+        match function.kind() {
+            /* cast<PHINode>(V) or llvm::cast<PHINode>(V) */
+            "template_function" | "qualified_identifier"
+                if template_name(&function, code)
+                    .is_some_and(|name| CAST_FN.contains(&name.as_str())) =>
+            {
+                let template = match function.kind() {
+                    "qualified_identifier" => function.child_by_field_name("name")?,
+                    _ => function,
+                };
+                let argument = template
+                    .child_by_field_name("arguments")?
+                    .named_child(0)?
+                    .child_by_field_name("type")?;
+                Some(class_name(&argument, code))
+            }
+            /* BinaryOperator::Create(...) */
+            "qualified_identifier" => {
+                let name = function.to_source(code);
+                (name.get_fn_kind(catalog) == Some(FnKind::Create))
+                    .then(|| name.rsplit_once("::").map(|(scope, _)| scope.to_string()))
+                    .flatten()
+            }
+            "field_expression" => {
+                let method = function.child_by_field_name("field")?.to_source(code);
+                if INSTRUCTION_ACCESSOR_FN.contains(&method.as_str()) {
+                    return Some("Instruction".to_string());
+                }
+                match method.get_fn_kind(catalog) {
+                    Some(FnKind::Clone) => Some("Instruction".to_string()),
+                    /* The others may be folded into a constant, or not, so unknown */
+                    Some(FnKind::BuilderCreate) => BUILDER_INSTRUCTION_FN
+                        .iter()
+                        .find(|(name, _)| *name == method)
+                        .map(|(_, class)| class.to_string()),
+                    _ if method == "getParent" => {
+                        let object = function.child_by_field_name("argument")?;
+                        match self.type_of(&object, code, catalog)?.as_str() {
+                            "BasicBlock" => Some("Function".to_string()),
+                            ty if is_instruction_type(ty) => Some("BasicBlock".to_string()),
+                            _ => None,
+                        }
+                    }
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// The class of the elements of a range, e.g., `Instruction` for `*BB`
+    /// or `make_early_inc_range(instructions(F))`
+    fn element_type(&self, range: &Node, code: &str, catalog: &Catalog) -> Option<String> {
+        if range.kind() == "call_expression" {
+            let function = range.child_by_field_name("function")?.to_source(code);
+            let function = function.rsplit("::").next().unwrap_or_default();
+            if function == "instructions" {
+                return Some("Instruction".to_string());
+            }
+            if RANGE_ADAPTOR_FN.contains(&function) {
+                let inner = range.child_by_field_name("arguments")?.named_child(0)?;
+                return self.element_type(&inner, code, catalog);
+            }
+        }
+        if range.kind() == "identifier" {
+            if let Some(elem) = self.lookup(range, code).and_then(|s| s.elem.clone()) {
+                return Some(elem);
+            }
+        }
+        match self.type_of(range, code, catalog)?.as_str() {
+            "BasicBlock" => Some("Instruction".to_string()),
+            "Function" => Some("BasicBlock".to_string()),
+            _ => None,
+        }
+    }
+}
+
+/// The name declared by a declarator, e.g., `PN` for `*PN = ...`
+fn declarator_name<'tree>(declarator: &Node<'tree>) -> Option<Node<'tree>> {
+    let mut node = *declarator;
+    loop {
+        match node.kind() {
+            "identifier" => return Some(node),
+            "init_declarator" | "pointer_declarator" => {
+                node = node.child_by_field_name("declarator")?
+            }
+            "reference_declarator" => node = node.named_child(0)?,
+            _ => return None,
+        }
+    }
+}
+
+/// The class named by a type, without its namespace or template arguments,
+/// e.g., `Instruction` for `llvm::Instruction`
+fn class_name(ty: &Node, code: &str) -> String {
+    match ty.kind() {
+        "qualified_identifier" => ty
+            .child_by_field_name("name")
+            .map_or_else(|| ty.to_source(code), |name| class_name(&name, code)),
+        "template_type" => ty
+            .child_by_field_name("name")
+            .map_or_else(|| ty.to_source(code), |name| name.to_source(code)),
+        _ => ty.to_source(code),
+    }
+}
+
+/// The name of a template function, e.g., `cast` for `llvm::cast<PHINode>`
+fn template_name(function: &Node, code: &str) -> Option<String> {
+    match function.kind() {
+        "template_function" => Some(function.child_by_field_name("name")?.to_source(code)),
+        "qualified_identifier" => template_name(&function.child_by_field_name("name")?, code),
+        _ => None,
+    }
+}
+
+fn scope_of(node: &Node) -> Range<usize> {
+    let mut parent = node.parent();
+    while let Some(scope) = parent {
+        if SCOPE_KINDS.contains(&scope.kind()) {
+            return scope.byte_range();
+        }
+        parent = scope.parent();
+    }
+    node.byte_range()
+}
@@ -0,0 +1,688 @@
+use std::{
+    any::Any,
+    fs,
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use clap::ValueEnum;
+use colored::Colorize;
+use glob::Pattern;
+use rayon::prelude::*;
+
+use crate::{
+    catalog::Catalog,
+    config, diff,
+    error::{Diagnostic, Status},
+    hook,
+    inst::Instrumenter,
+    manifest::{self, Manifest, RestoreStatus},
+    report::Report,
+    strip::Stripper,
+};
+
+const CPP_EXTS: [&str; 3] = ["cpp", "cc", "cxx"];
+
+/// Copy the monitor library into `llvm/include/llvm/Transforms/Utils/`
+pub fn setup(llvm: &Path) -> Status {
+    let library_path = config::library_path(llvm);
+    if library_path.exists() {
+        println!(
+            "{} The library already exists, skipping",
+            "Finished".green().bold()
+        );
+        return Status::Success;
+    }
+
+    println!("{} {}", "Creating".green().bold(), library_path.display());
+    if let Err(e) = fs::write(&library_path, hook::LIBRARY) {
+        Diagnostic::error(format!("failed to create the library: {}", e)).emit();
+        return Status::Failed;
+    }
+    Status::Success
+}
+
+/// Glob patterns selecting the files to instrument,
+/// matched against the paths relative to the target directory
+pub struct FileFilter {
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>,
+}
+
+impl FileFilter {
+    pub fn new(include: &[String], exclude: &[String]) -> Result<Self, String> {
+        let compile = |patterns: &[String]| {
+            patterns
+                .iter()
+                .map(|p| Pattern::new(p).map_err(|e| format!("Invalid glob `{}`: {}", p, e)))
+                .collect::<Result<Vec<_>, _>>()
+        };
+        Ok(Self {
+            include: compile(include)?,
+            exclude: compile(exclude)?,
+        })
+    }
+
+    fn accepts(&self, relative_path: &Path) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|p| p.matches_path(relative_path)))
+            && !self.exclude.iter().any(|p| p.matches_path(relative_path))
+    }
+}
+
+/// What to do with the files that have been instrumented before
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Existing {
+    /// Leave them as they are
+    Skip,
+    /// Fail on them
+    Refuse,
+    /// Instrument their original content again, recorded when instrumented
+    /// in place or recovered by stripping the hooks
+    Reinstrument,
+}
+
+/// Where the instrumented files go
+pub enum Output<'a> {
+    /// Mirror the layout under the target in the directory
+    Dir(&'a Path),
+    /// Overwrite the pass files, recording their original content in the manifest
+    InPlace(&'a mut Manifest),
+    /// Print the planned instrumentation as a diff without writing anything
+    Diff,
+}
+
+/// Instrument the pass file(s) under `target`, walking its subdirectories.
+///
+/// With `report_dir`, the sites found in each file are reported as JSON there,
+/// mirroring the layout under the target. Files calling hooks already are
+/// handled according to `existing`, so that instrumenting twice is harmless.
+pub fn instrument(
+    target: &Path,
+    mut output: Output,
+    filter: &FileFilter,
+    report_dir: Option<&Path>,
+    existing: Existing,
+    project: bool,
+    catalog: &Catalog,
+) -> Status {
+    if !target.exists() {
+        Diagnostic::error(format!("{} does not exist", target.display())).emit();
+        return Status::Invalid;
+    }
+
+    if target.is_file() && !is_cpp(target) {
+        println!("{} only instrument C++ files!", "Warning".yellow().bold());
+    }
+
+    let work_list: Vec<(PathBuf, PathBuf)> = cpp_files(target)
+        .into_iter()
+        .filter(|(_, relative_path)| filter.accepts(relative_path))
+        .collect();
+
+    if work_list.is_empty() {
+        println!("{} No file to instrument, exit.", "Finished".green().bold());
+        return Status::NothingToInstrument;
+    }
+
+    let manifest = match &output {
+        Output::InPlace(manifest) => Some(&**manifest),
+        _ => None,
+    };
+
+    /* Instrument in parallel with one parser per worker, isolating the panics of each file */
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| {}));
+    let outcomes: Vec<Outcome> = work_list
+        .par_iter()
+        .map_init(
+            || Instrumenter::new(catalog),
+            |instrumenter, (path, _)| {
+                panic::catch_unwind(AssertUnwindSafe(|| {
+                    instrument_file(instrumenter, path, manifest, existing)
+                }))
+                .unwrap_or_else(|payload| {
+                    /* The parser may be left in the middle of a file */
+                    *instrumenter = Instrumenter::new(catalog);
+                    Outcome::Failed(Diagnostic::error(panic_message(payload)))
+                })
+            },
+        )
+        .collect();
+    panic::set_hook(default_hook);
+
+    /* A project is one pass split across the files, which runs only if some file has its entry,
+     * though an entry instrumented before is skipped */
+    let failed = outcomes.iter().any(|o| matches!(o, Outcome::Failed(_)));
+    if project
+        && !failed
+        && !outcomes.iter().any(|o| match o {
+            Outcome::Instrumented(_, _, report) => report.entries > 0,
+            Outcome::Skipped(_, has_entry) => *has_entry,
+            Outcome::Failed(_) => false,
+        })
+    {
+        Diagnostic::error("no pass entry in the project, so none of its hooks would run").emit();
+        return Status::Invalid;
+    }
+
+    let mut summary = Summary::default();
+    for ((path, relative_path), outcome) in work_list.iter().zip(outcomes) {
+        let (original, code, mut report) = match outcome {
+            /* A project is instrumented as a whole, or not at all */
+            Outcome::Instrumented(..) if project && failed => {
+                println!(
+                    "{} {} is left untouched with the failed files of the project",
+                    "Warning".yellow().bold(),
+                    path.display()
+                );
+                summary.skipped += 1;
+                continue;
+            }
+            Outcome::Instrumented(original, code, report) => (original, code, report),
+            Outcome::Skipped(reason, _) => {
+                println!(
+                    "{} {} {}",
+                    "Warning".yellow().bold(),
+                    path.display(),
+                    reason
+                );
+                summary.skipped += 1;
+                continue;
+            }
+            Outcome::Failed(diag) => {
+                summary.failed.push((path.clone(), diag));
+                continue;
+            }
+        };
+
+        /* Nothing to track, e.g., a file calling none of the APIs matched, is left untouched,
+         * unless it has the entry of a project */
+        if report.instrumented == 0 && !(project && report.entries > 0) {
+            report.warnings.iter().for_each(Diagnostic::emit);
+            if let Err(msg) = write_report(relative_path, &report, report_dir) {
+                summary.failed.push((path.clone(), Diagnostic::error(msg)));
+            }
+            summary.untouched += 1;
+            continue;
+        }
+
+        if !matches!(output, Output::Diff) {
+            println!("{} {}", "Instrumenting".green().bold(), path.display());
+        }
+        if !project && report.entries == 0 {
+            report.warnings.push(Diagnostic::warning(format!(
+                "no pass entry in {}, whose hooks only run under an entry instrumented with it, \
+                 see `--project`",
+                relative_path.display()
+            )));
+        }
+        report.warnings.iter().for_each(Diagnostic::emit);
+        match write_file(
+            &mut output,
+            path,
+            relative_path,
+            &original,
+            &code,
+            &report,
+            report_dir,
+        ) {
+            Ok(()) => {
+                summary.instrumented += 1;
+            }
+            Err(msg) => summary.failed.push((path.clone(), Diagnostic::error(msg))),
+        }
+    }
+
+    summary.print();
+    summary.status()
+}
+
+/// The result of instrumenting one file
+enum Outcome {
+    /// Original code, instrumented code and the report
+    Instrumented(String, String, Report),
+    /// Why the file is skipped, and whether it has an entry instrumented before
+    Skipped(String, bool),
+    Failed(Diagnostic),
+}
+
+#[derive(Default)]
+struct Summary {
+    instrumented: usize,
+    /// Number of the files left untouched for having no site
+    untouched: usize,
+    skipped: usize,
+    failed: Vec<(PathBuf, Diagnostic)>,
+}
+
+impl Summary {
+    fn print(&self) {
+        println!(
+            "{} {} instrumented, {} without sites, {} skipped, {} failed",
+            "Finished".green().bold(),
+            self.instrumented,
+            self.untouched,
+            self.skipped,
+            self.failed.len()
+        );
+        for (path, diag) in &self.failed {
+            eprintln!("{} {}", "Failed".red().bold(), path.display());
+            diag.emit();
+        }
+    }
+
+    fn status(&self) -> Status {
+        if !self.failed.is_empty() {
+            Status::Failed
+        } else if self.instrumented == 0 {
+            Status::NothingToInstrument
+        } else {
+            Status::Success
+        }
+    }
+}
+
+fn instrument_file(
+    instrumenter: &mut Instrumenter,
+    path: &Path,
+    manifest: Option<&Manifest>,
+    existing: Existing,
+) -> Outcome {
+    let filename = file_name(path);
+    let mut original = match fs::read_to_string(path) {
+        Ok(original) => original,
+        Err(e) => {
+            return Outcome::Failed(Diagnostic::error(format!("failed to read the file: {}", e)))
+        }
+    };
+    let entry = manifest
+        .zip(path.canonicalize().ok())
+        .and_then(|(manifest, path)| manifest.find(&path));
+    let unedited = entry.is_some_and(|entry| manifest::hash(&original) == entry.instrumented_hash);
+
+    if entry.is_some() || instrumenter.is_instrumented(&original) {
+        match existing {
+            Existing::Skip => {
+                let reason = if entry.is_none() || unedited {
+                    "has already been instrumented"
+                } else {
+                    "has been edited after instrumentation, restore it first"
+                };
+                return Outcome::Skipped(reason.to_string(), instrumenter.has_entry(&original));
+            }
+            Existing::Refuse => {
+                return Outcome::Failed(Diagnostic::error("the file has already been instrumented"))
+            }
+            Existing::Reinstrument => {
+                /* Prefer the recorded original, which is exact, to stripping the hooks */
+                if let (Some(manifest), Some(entry), true) = (manifest, entry, unedited) {
+                    original = match manifest.original(entry) {
+                        Ok(original) => original,
+                        Err(msg) => return Outcome::Failed(Diagnostic::error(msg)),
+                    };
+                } else if let Err(diag) = Stripper::new().strip(&filename, &mut original) {
+                    return Outcome::Failed(diag);
+                }
+            }
+        }
+    }
+
+    let mut code = original.clone();
+    match instrumenter.instrument(&filename, &mut code) {
+        Ok(report) => Outcome::Instrumented(original, code, report),
+        Err(diag) => Outcome::Failed(diag),
+    }
+}
+
+fn write_file(
+    output: &mut Output,
+    path: &Path,
+    relative_path: &Path,
+    original: &str,
+    code: &str,
+    report: &Report,
+    report_dir: Option<&Path>,
+) -> Result<(), String> {
+    write_report(relative_path, report, report_dir)?;
+
+    match output {
+        Output::Dir(output_dir) => write_creating_dirs(&output_dir.join(relative_path), code),
+        Output::InPlace(manifest) => {
+            /* Record before overwriting so that the original is never lost */
+            let path = path
+                .canonicalize()
+                .map_err(|e| format!("Failed to resolve {}: {}", path.display(), e))?;
+            manifest
+                .record(&path, original, code)
+                .and_then(|_| manifest.save())?;
+            fs::write(path, code).map_err(|e| format!("Failed to write the file: {}", e))
+        }
+        Output::Diff => {
+            diff::print_unified_diff(&relative_path.to_string_lossy(), original, code);
+            Ok(())
+        }
+    }
+}
+
+/// Report the sites of the file as `<file>.json` under the report directory, if any
+fn write_report(
+    relative_path: &Path,
+    report: &Report,
+    report_dir: Option<&Path>,
+) -> Result<(), String> {
+    let Some(report_dir) = report_dir else {
+        return Ok(());
+    };
+    let mut report_path = report_dir.join(relative_path).into_os_string();
+    report_path.push(".json");
+    write_creating_dirs(&PathBuf::from(report_path), &report.to_json())
+}
+
+fn write_creating_dirs(path: &Path, content: &str) -> Result<(), String> {
+    fs::create_dir_all(path.parent().unwrap())
+        .and_then(|_| fs::write(path, content))
+        .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
+
+/// The name of the file given to the hooks and the diagnostics, which may not be UTF-8
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .unwrap_or(path.as_os_str())
+        .to_string_lossy()
+        .into_owned()
+}
+
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    if let Some(msg) = payload.downcast_ref::<&str>() {
+        msg.to_string()
+    } else if let Some(msg) = payload.downcast_ref::<String>() {
+        msg.clone()
+    } else {
+        "Unknown panic".to_string()
+    }
+}
+
+/// Strip the hooks from the instrumented file(s) under `target`, writing the
+/// stripped files to `output` mirroring the layout under the target, or in place.
+///
+/// With `check`, a file fails unless instrumenting the stripped code gives it back,
+/// i.e., the stripped code is exactly the original.
+pub fn strip(target: &Path, output: Option<&Path>, check: bool, catalog: &Catalog) -> Status {
+    if !target.exists() {
+        Diagnostic::error(format!("{} does not exist", target.display())).emit();
+        return Status::Invalid;
+    }
+
+    let mut stripper = Stripper::new();
+    let mut instrumenter = Instrumenter::new(catalog);
+    let mut stripped_files = 0;
+    let mut failed = vec![];
+    for (path, relative_path) in cpp_files(target) {
+        let filename = file_name(&path);
+        let code = match fs::read_to_string(&path) {
+            Ok(code) => code,
+            Err(e) => {
+                failed.push((
+                    path,
+                    Diagnostic::error(format!("failed to read the file: {}", e)),
+                ));
+                continue;
+            }
+        };
+
+        let mut stripped = code.clone();
+        match stripper.strip(&filename, &mut stripped) {
+            Ok(0) => continue,
+            Ok(_) => {}
+            Err(diag) => {
+                failed.push((path, diag));
+                continue;
+            }
+        }
+
+        if check {
+            let mut reinstrumented = stripped.clone();
+            if let Err(diag) = instrumenter.instrument(&filename, &mut reinstrumented) {
+                failed.push((path, diag));
+                continue;
+            }
+            if reinstrumented != code {
+                failed.push((
+                    path,
+                    Diagnostic::error(
+                        "the stripped code does not instrument back to the file, \
+                         which may have been edited after instrumentation",
+                    ),
+                ));
+                continue;
+            }
+        }
+
+        println!("{} {}", "Stripping".green().bold(), path.display());
+        let output_path = output.map_or_else(|| path.clone(), |dir| dir.join(&relative_path));
+        match write_creating_dirs(&output_path, &stripped) {
+            Ok(()) => stripped_files += 1,
+            Err(msg) => failed.push((path, Diagnostic::error(msg))),
+        }
+    }
+
+    println!(
+        "{} {} stripped, {} failed",
+        "Finished".green().bold(),
+        stripped_files,
+        failed.len()
+    );
+    for (path, diag) in &failed {
+        eprintln!("{} {}", "Failed".red().bold(), path.display());
+        diag.emit();
+    }
+
+    if !failed.is_empty() {
+        Status::Failed
+    } else if stripped_files == 0 {
+        Status::NothingToInstrument
+    } else {
+        Status::Success
+    }
+}
+
+/// Print the effective API catalog, which can be edited and configured as `catalog`
+pub fn show_catalog(catalog: &Catalog) -> Status {
+    let version = catalog
+        .version
+        .map_or("latest".to_string(), |version| version.to_string());
+    let profiles: Vec<String> = catalog.profiles.iter().map(u32::to_string).collect();
+    println!("# Catalog: {}", catalog.source);
+    println!("# LLVM version: {}", version);
+    println!(
+        "# Profiles applied: {}",
+        if profiles.is_empty() {
+            "none".to_string()
+        } else {
+            profiles.join(", ")
+        }
+    );
+    print!("{}", catalog.to_toml());
+    Status::Success
+}
+
+/// Run the `RUN:` lines of the regression tests under `test_path` with the instrumented `opt`
+pub fn analyze(opt: &Path, test_path: &Path) -> Status {
+    let mut status = Status::Success;
+    println!(
+        "{} tests under {}",
+        "Running".green().bold(),
+        test_path.display()
+    );
+
+    for test_case in walk_files(test_path) {
+        if test_case.extension().is_none_or(|ext| ext != "ll") {
+            continue;
+        }
+        println!("{} test {}", "Running".green().bold(), test_case.display());
+
+        let content = match fs::read_to_string(&test_case) {
+            Ok(content) => content,
+            Err(e) => {
+                println!(
+                    "{} Failed to read {}: {}",
+                    "Warning".yellow().bold(),
+                    test_case.display(),
+                    e
+                );
+                continue;
+            }
+        };
+
+        for line in content.lines().filter(|line| line.starts_with("; RUN:")) {
+            let command = synthesize_cmd(line, &test_case, opt);
+            println!("{} command: {}", "Running".green().bold(), command);
+
+            match Command::new("sh").arg("-c").arg(&command).output() {
+                Ok(output) if output.status.success() => {
+                    print!("{}", String::from_utf8_lossy(&output.stdout));
+                    eprint!("{}", String::from_utf8_lossy(&output.stderr));
+                }
+                Ok(output) => {
+                    Diagnostic::error(format!("command {} failed with {}", command, output.status))
+                        .emit();
+                    status = Status::Failed;
+                }
+                Err(e) => {
+                    Diagnostic::error(format!("command {} failed with error {}", command, e))
+                        .emit();
+                    status = Status::Failed;
+                }
+            }
+        }
+    }
+    status
+}
+
+/// Put back the files instrumented in place.
+///
+/// The files edited after instrumentation are kept unless `force` is set.
+pub fn restore(manifest: &mut Manifest, force: bool) -> Status {
+    let mut result = Status::Success;
+    for (path, status) in manifest.restore(force) {
+        match status {
+            RestoreStatus::Restored => {
+                println!("{} {}", "Restored".green().bold(), path.display())
+            }
+            RestoreStatus::Unchanged => println!(
+                "{} {} is not instrumented, skipping",
+                "Finished".green().bold(),
+                path.display()
+            ),
+            RestoreStatus::Modified => {
+                Diagnostic::warning(format!(
+                    "{} has been edited after instrumentation, use --force to overwrite it",
+                    path.display()
+                ))
+                .emit();
+                result = Status::Failed;
+            }
+            RestoreStatus::Missing => Diagnostic::warning(format!(
+                "{} no longer exists, keeping its backup",
+                path.display()
+            ))
+            .emit(),
+            RestoreStatus::Failed(diag) => {
+                diag.emit();
+                result = Status::Failed;
+            }
+        }
+    }
+    if let Err(msg) = manifest.save() {
+        Diagnostic::error(msg).emit();
+        return Status::Failed;
+    }
+    result
+}
+
+/// Remove the library and restore the files instrumented in place
+pub fn clean(llvm: &Path, manifest: &mut Manifest) -> Status {
+    let library_path = config::library_path(llvm);
+    if library_path.exists() {
+        println!("{} the library", "Removing".green().bold());
+        if let Err(e) = fs::remove_file(&library_path) {
+            Diagnostic::error(format!("failed to remove the library: {}", e)).emit();
+            return Status::Failed;
+        }
+    }
+
+    restore(manifest, false)
+}
+
+/// Turn `; RUN: opt -passes=xxx < %s | FileCheck %s` into a runnable command
+/// that executes `debugify,xxx` on the test case without output
+fn synthesize_cmd(line: &str, test_case: &Path, opt: &Path) -> String {
+    let test_case = test_case.to_string_lossy();
+    let command = line
+        .split_once("; RUN:")
+        .map_or(line, |(_, cmd)| cmd)
+        .split('|')
+        .next()
+        .unwrap()
+        .trim();
+    let command = command
+        .replace("< %s", &test_case)
+        .replace("%s", &test_case)
+        .replacen("opt", &format!("{} --disable-output", opt.display()), 1);
+
+    if command.contains("-passes=\"") {
+        command.replace("-passes=\"", "-passes=\"debugify,")
+    } else if command.contains("-passes='") {
+        command.replace("-passes='", "-passes='debugify,")
+    } else {
+        command.replace("-passes=", "-passes=debugify,")
+    }
+}
+
+fn is_cpp(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| CPP_EXTS.iter().any(|cpp_ext| ext == *cpp_ext))
+}
+
+/// The C++ file `target`, or the C++ files under it, each with its path relative
+/// to the target, in order
+fn cpp_files(target: &Path) -> Vec<(PathBuf, PathBuf)> {
+    let mut files: Vec<(PathBuf, PathBuf)> = if target.is_file() {
+        vec![(
+            target.to_path_buf(),
+            PathBuf::from(target.file_name().unwrap_or(target.as_os_str())),
+        )]
+    } else {
+        walk_files(target)
+            .into_iter()
+            .map(|path| {
+                let relative_path = path.strip_prefix(target).unwrap().to_path_buf();
+                (path, relative_path)
+            })
+            .collect()
+    };
+    files.retain(|(path, _)| is_cpp(path));
+    files.sort();
+    files
+}
+
+/// All the files under `dir` and its subdirectories
+fn walk_files(dir: &Path) -> Vec<PathBuf> {
+    let mut files = vec![];
+    if dir.is_file() {
+        files.push(dir.to_path_buf());
+        return files;
+    }
+    if let Ok(entries) = dir.read_dir() {
+        for e in entries.flatten() {
+            let path = e.path();
+            if path.is_dir() {
+                files.append(&mut walk_files(&path));
+            } else {
+                files.push(path);
+            }
+        }
+    }
+    files
+}
@@ -1,3 +1,5 @@
+use std::collections::HashSet;
+
 use tree_sitter::Node;
 
 use crate::ast::AstNode;
@@ -15,7 +17,21 @@ pub fn get_children_of_kind<'tree>(node: &Node<'tree>, kind: &str) -> Vec<Node<'
     res
 }
 
-/// Return the closest
+/// All the names used under the node, whether of variables, fields, types or namespaces
+pub fn get_identifiers(node: &Node, code: &str) -> HashSet<String> {
+    let mut res = HashSet::new();
+    for cid in 0..node.child_count() {
+        let child = node.child(cid).unwrap();
+        if child.kind().ends_with("identifier") {
+            res.insert(child.to_source(code));
+        }
+        res.extend(get_identifiers(&child, code));
+    }
+    res
+}
+
+/// Return the closest in the function or lambda the node is in, e.g., the
+/// declaration of `auto F = [](...) { ... };` is not the one of the calls in `F`
 pub fn get_parent_of_kind<'tree>(node: &Node<'tree>, kind: &str) -> Option<Node<'tree>> {
     let mut nullable_parent = node.parent();
     let mut the_parent = None;
@@ -25,44 +41,49 @@ pub fn get_parent_of_kind<'tree>(node: &Node<'tree>, kind: &str) -> Option<Node<
             the_parent = Some(parent);
             break;
         }
+        if matches!(parent.kind(), "lambda_expression" | "function_definition") {
+            break;
+        }
         nullable_parent = parent.parent();
     }
     the_parent
 }
 
-pub fn get_var_name_from_decl<'tree>(decl: &Node<'tree>) -> Node<'tree> {
-    let var_name = decl
-        .child_by_field_name("declarator")
-        .unwrap()
-        .child_by_field_name("declarator")
-        .unwrap();
-    if var_name.kind() == "pointer_declarator" {
-        var_name.child_by_field_name("declarator").unwrap()
-    } else {
-        var_name
+/// The variable declared by an init declarator, or by a declaration in a condition,
+/// e.g., `NewI` for `*NewI = I->clone()`, but none for a structured binding
+pub fn get_var_name_from_declarator<'tree>(declarator: &Node<'tree>) -> Option<Node<'tree>> {
+    let mut var_name = declarator.child_by_field_name("declarator")?;
+    loop {
+        match var_name.kind() {
+            "identifier" => return Some(var_name),
+            "pointer_declarator" => var_name = var_name.child_by_field_name("declarator")?,
+            "reference_declarator" => var_name = var_name.named_child(0)?,
+            _ => return None,
+        }
     }
 }
 
-pub fn get_var_name_from_assign<'tree>(assign: &Node<'tree>) -> Node<'tree> {
-    assert_eq!(assign.kind(), "assignment_expression");
-    let var_name = assign.child_by_field_name("left").unwrap();
+pub fn get_var_name_from_assign<'tree>(assign: &Node<'tree>) -> Option<Node<'tree>> {
+    if assign.kind() != "assignment_expression" {
+        return None;
+    }
+    let var_name = assign.child_by_field_name("left")?;
     if var_name.kind() == "pointer_declarator" {
-        var_name.child_by_field_name("declarator").unwrap()
+        var_name.child_by_field_name("declarator")
     } else {
-        var_name
+        Some(var_name)
     }
 }
 
-pub fn get_fn_identifier<'tree>(fn_def: &Node<'tree>) -> Node<'tree> {
+pub fn get_fn_identifier<'tree>(fn_def: &Node<'tree>) -> Option<Node<'tree>> {
     // Only one function declarator in one function definition
-    let declarator = get_children_of_kind(fn_def, "function_declarator")[0];
-    let identifier = declarator.child_by_field_name("declarator").unwrap();
-    let identifier = if identifier.kind() == "function_declarator" {
-        identifier.child_by_field_name("declarator").unwrap()
+    let declarator = *get_children_of_kind(fn_def, "function_declarator").first()?;
+    let identifier = declarator.child_by_field_name("declarator")?;
+    if identifier.kind() == "function_declarator" {
+        identifier.child_by_field_name("declarator")
     } else {
-        identifier
-    };
-    identifier
+        Some(identifier)
+    }
 }
 
 #[allow(dead_code)]
@@ -71,15 +92,17 @@ pub fn get_ident_from_call<'tree>(
     fn_name_str: &str,
     code: &str,
 ) -> Option<Node<'tree>> {
-    assert_eq!(fn_call.kind(), "call_expression");
+    if fn_call.kind() != "call_expression" {
+        return None;
+    }
 
-    let function = fn_call.child_by_field_name("function").unwrap();
+    let function = fn_call.child_by_field_name("function")?;
     if function.kind() == "field_expression" {
-        let ident = function.child_by_field_name("argument").unwrap();
-        let fn_name = function.child_by_field_name("field").unwrap();
+        let ident = function.child_by_field_name("argument")?;
+        let fn_name = function.child_by_field_name("field")?;
         if fn_name.to_source(code) == fn_name_str {
             return Some(ident);
         }
     }
     None
-}
\ No newline at end of file
+}